@@ -0,0 +1,18 @@
+/// The fixed-seed linear congruential generator that the benchmarks build their synthetic inputs
+/// with, so that every run measures the same input.
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg(seed)
+    }
+
+    /// A number below `bound`, from the high bits of the state, which are the most random.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
@@ -0,0 +1,38 @@
+use advent_of_code_2024::day1::{sort_columns, SortStrategy};
+use common::Lcg;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+mod common;
+
+/// Builds two columns of `rows` pseudo-random five-digit location IDs from a fixed-seed LCG.
+fn synthetic_columns(rows: usize) -> Vec<Vec<u32>> {
+    let mut rng = Lcg::new(0x2024_0001);
+    let mut next_id = || 10_000 + rng.below(90_000) as u32;
+    (0..2)
+        .map(|_| (0..rows).map(|_| next_id()).collect())
+        .collect()
+}
+
+fn sort_strategies(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day1_sort");
+    group.sample_size(10);
+    for rows in [1_000, 10_000, 100_000, 1_000_000, 10_000_000] {
+        let columns = synthetic_columns(rows);
+        for (name, strategy) in [
+            ("comparison", SortStrategy::Comparison),
+            ("parallel_radix", SortStrategy::ParallelRadix),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, rows), &columns, |b, columns| {
+                b.iter_batched_ref(
+                    || columns.clone(),
+                    |columns| sort_columns(columns, strategy),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sort_strategies);
+criterion_main!(benches);
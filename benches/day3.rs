@@ -0,0 +1,50 @@
+use advent_of_code_2024::day3::InstructionSet;
+use common::Lcg;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+mod common;
+
+/// Builds `len` bytes of corrupted memory from a fixed-seed LCG: mostly printable noise, with
+/// valid and broken `mul`, `do()` and `don't()` instructions mixed in.
+fn synthetic_memory(len: usize) -> String {
+    const FRAGMENTS: [&str; 8] = [
+        "mul(2,4)",
+        "mul(123,45)",
+        "mul[3,7]",
+        "do()",
+        "don't()",
+        "mul(32,64]",
+        "mu",
+        "what()",
+    ];
+    const NOISE: &[u8; 33] = b"!@#$%^&*()_+-=[]{};:',.<>/?~ xwhy";
+    let mut rng = Lcg::new(0x2024_0003);
+    let mut memory = String::with_capacity(len + 16);
+    while memory.len() < len {
+        if rng.below(4) == 0 {
+            memory.push_str(FRAGMENTS[rng.below(FRAGMENTS.len() as u64) as usize]);
+        } else {
+            memory.push(NOISE[rng.below(NOISE.len() as u64) as usize] as char);
+        }
+    }
+    memory
+}
+
+fn scanners(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day3_scan");
+    let set = InstructionSet::conditional();
+    for len in [10_000, 1_000_000, 10_000_000] {
+        let memory = synthetic_memory(len);
+        group.throughput(Throughput::Bytes(memory.len() as u64));
+        group.bench_with_input(BenchmarkId::new("regex", len), &memory, |b, memory| {
+            b.iter(|| set.tokenize_regex(memory).count())
+        });
+        group.bench_with_input(BenchmarkId::new("memchr", len), &memory, |b, memory| {
+            b.iter(|| set.tokenize(memory).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scanners);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use advent_of_code_2024::day9;
+use common::Lcg;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+mod common;
+
+/// Builds a disk map with `entries` files, each followed by a gap, from a fixed-seed LCG.
+fn synthetic_disk_map(entries: usize) -> String {
+    let mut rng = Lcg::new(0x2024_0009);
+    let mut next_digit = |min: u8| char::from(b'0' + min + rng.below(10 - min as u64) as u8);
+    (0..entries)
+        .flat_map(|_| [next_digit(1), next_digit(0)])
+        .collect()
+}
+
+fn part2_gap_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day9_part2");
+    group.sample_size(10);
+    for entries in [10_000, 100_000, 1_000_000] {
+        let input = synthetic_disk_map(entries);
+        group.bench_with_input(BenchmarkId::new("heap", entries), &input, |b, input| {
+            b.iter(|| day9::part2(black_box(input)))
+        });
+        group.bench_with_input(BenchmarkId::new("deque", entries), &input, |b, input| {
+            b.iter(|| day9::part2_deque(black_box(input)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, part2_gap_index);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+use advent_of_code_2024::common::{exit_with, read_input};
+use advent_of_code_2024::day1::{render_report, ReportFormat, ReportKind};
+use std::env;
+
+const USAGE: &str = "usage: day1_report <distance|similarity> <csv|json> [input]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (kind, format) = match (
+        args.first().map(String::as_str),
+        args.get(1).map(String::as_str),
+    ) {
+        (Some("distance"), Some(format)) => (ReportKind::Distance, format),
+        (Some("similarity"), Some(format)) => (ReportKind::Similarity, format),
+        _ => exit_with(USAGE),
+    };
+    let format = match format {
+        "csv" => ReportFormat::Csv,
+        "json" => ReportFormat::Json,
+        _ => exit_with(USAGE),
+    };
+
+    let input = read_input(args.get(2).map(String::as_str));
+
+    match render_report(&input, kind, format) {
+        Ok(report) => print!("{}", report),
+        Err(error) => exit_with(&format!("error: {}", error)),
+    }
+}
@@ -0,0 +1,200 @@
+use advent_of_code_2024::common::{exit_with, read_input};
+use advent_of_code_2024::day2::{
+    classify_reports, classify_stream, count_safe_with_removals, mark_violation, part1_with_policy,
+    part2_with_policy, unsafe_reports, Direction, SafetyPolicy,
+};
+use advent_of_code_2024::error::AocError;
+use itertools::Itertools;
+use std::{env, fs, io, thread};
+
+const USAGE: &str = "usage: day2 [--policy FILE] [--min-step N] [--max-step N] [--allow-equal] \
+[--direction ascending|descending|either] [--max-removals K] [--violations] [--repairs]
+[--stream [--workers N] [--block-size BYTES]] [input]
+
+Counts safe reports, and reports that are safe with the problem dampener, which removes at most
+one level or K levels if given. Flags override the values read from the TOML policy file.
+
+With --violations, prints each unsafe report with its first violating step in brackets instead,
+followed by the number of unsafe reports with each cause.
+
+With --repairs, prints the smallest single edit that makes each unsafe report safe instead,
+followed by the number of reports in each repairability class.
+
+With --stream, reads the input in blocks and classifies them on N worker threads (one per CPU by
+default), printing the running safe, dampened and unsafe counts as each block is done. The
+dampener removes at most one level, so --stream cannot be combined with --max-removals,
+--violations or --repairs.";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut policy = SafetyPolicy::default();
+    let mut overrides: Vec<(String, String)> = vec![];
+    let mut input_path = None;
+    let mut max_removals = 1;
+    let mut show_violations = false;
+    let mut show_repairs = false;
+    let mut stream = false;
+    let mut workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut block_size = 64 * 1024;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow-equal" => overrides.push((arg, String::new())),
+            "--violations" => show_violations = true,
+            "--repairs" => show_repairs = true,
+            "--stream" => stream = true,
+            "--workers" | "--block-size" => {
+                let value = args.next().unwrap_or_else(|| exit_with(USAGE));
+                let count = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| exit_with(&format!("error: invalid count {:?}", value)));
+                match arg.as_str() {
+                    "--workers" => workers = count,
+                    _ => block_size = count,
+                }
+            }
+            "--policy" | "--min-step" | "--max-step" | "--direction" | "--max-removals" => {
+                let value = args.next().unwrap_or_else(|| exit_with(USAGE));
+                if arg == "--policy" {
+                    let source = fs::read_to_string(&value)
+                        .unwrap_or_else(|error| exit_with(&format!("error: {}: {}", value, error)));
+                    policy = SafetyPolicy::from_toml(&source)
+                        .unwrap_or_else(|error| exit_with(&format!("error: {}: {}", value, error)));
+                } else {
+                    overrides.push((arg, value));
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") || input_path.is_some() => exit_with(USAGE),
+            _ => input_path = Some(arg),
+        }
+    }
+
+    let max_removals_given = overrides.iter().any(|(flag, _)| flag == "--max-removals");
+    if stream && (max_removals_given || show_violations || show_repairs) {
+        exit_with(&format!(
+            "error: --stream cannot be combined with --max-removals, --violations or --repairs\n\n{}",
+            USAGE
+        ));
+    }
+
+    for (flag, value) in overrides {
+        match flag.as_str() {
+            "--allow-equal" => policy.allow_equal = true,
+            "--min-step" => policy.min_step = parse_step(&value),
+            "--max-step" => policy.max_step = parse_step(&value),
+            "--max-removals" => {
+                max_removals = value
+                    .parse()
+                    .unwrap_or_else(|_| exit_with(&format!("error: invalid count {:?}", value)))
+            }
+            "--direction" => {
+                policy.direction = match value.as_str() {
+                    "ascending" => Direction::Ascending,
+                    "descending" => Direction::Descending,
+                    "either" => Direction::Either,
+                    _ => exit_with(USAGE),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if stream {
+        if let Err(error) = print_stream(input_path, &policy, block_size, workers) {
+            exit_with(&format!("error: {}", error));
+        }
+        return;
+    }
+
+    let input = read_input(input_path.as_deref());
+
+    let result = if show_violations {
+        print_violations(&input, &policy)
+    } else if show_repairs {
+        print_repairs(&input, &policy)
+    } else {
+        print_counts(&input, &policy, max_removals)
+    };
+    if let Err(error) = result {
+        exit_with(&format!("error: {}", error));
+    }
+}
+
+fn print_counts(input: &str, policy: &SafetyPolicy, max_removals: usize) -> Result<(), AocError> {
+    println!("safe: {}", part1_with_policy(input, policy)?);
+    if max_removals == 1 {
+        println!("safe with dampener: {}", part2_with_policy(input, policy)?);
+    } else {
+        println!(
+            "safe with up to {} removals: {}",
+            max_removals,
+            count_safe_with_removals(input, policy, max_removals)?
+        );
+    }
+    Ok(())
+}
+
+fn print_stream(
+    path: Option<String>,
+    policy: &SafetyPolicy,
+    block_size: usize,
+    workers: usize,
+) -> Result<(), AocError> {
+    let print = |counts| println!("{}", counts);
+    let counts = match path {
+        Some(path) => classify_stream(fs::File::open(path)?, policy, block_size, workers, print),
+        None => classify_stream(io::stdin(), policy, block_size, workers, print),
+    }?;
+    println!("total: {}", counts);
+    Ok(())
+}
+
+fn print_violations(input: &str, policy: &SafetyPolicy) -> Result<(), AocError> {
+    let mut causes = vec![];
+    for unsafe_report in unsafe_reports(input, policy) {
+        let (line, report, violation) = unsafe_report?;
+        println!(
+            "line {}: {}  {}",
+            line,
+            mark_violation(&report, &violation),
+            violation
+        );
+        causes.push(violation.cause());
+    }
+    for (cause, count) in causes.into_iter().counts().into_iter().sorted() {
+        println!("{}: {}", cause, count);
+    }
+    Ok(())
+}
+
+fn print_repairs(input: &str, policy: &SafetyPolicy) -> Result<(), AocError> {
+    for unsafe_report in unsafe_reports(input, policy) {
+        let (line, report, _) = unsafe_report?;
+        let repair = match policy.minimal_repair(&report) {
+            Some(edit) => edit.to_string(),
+            None => "no single edit".to_string(),
+        };
+        println!("line {}: {}  {}", line, report.iter().join(" "), repair);
+    }
+    for (class, count) in classify_reports(input, policy)?
+        .into_iter()
+        .counts()
+        .into_iter()
+        .sorted()
+    {
+        println!("{:?}: {}", class, count);
+    }
+    Ok(())
+}
+
+fn parse_step(value: &str) -> u32 {
+    value
+        .parse()
+        .unwrap_or_else(|_| exit_with(&format!("error: invalid step {:?}", value)))
+}
@@ -0,0 +1,53 @@
+use advent_of_code_2024::common::{exit_with, read_input};
+use advent_of_code_2024::day3::{render_trace, Grammar, InstructionSet, OperandRule, TraceFormat};
+use std::{env, fs};
+
+const USAGE: &str =
+    "usage: day3_trace <ansi|html|json> [--extended | --grammar FILE] [--lenient] [input]
+
+Shows which instructions in the corrupted memory counted and which byte ranges were disabled,
+as highlighted terminal output, an HTML fragment or a JSON trace. The instruction set is the
+puzzle's, the extended one, or one described by a TOML grammar file.";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let format = match args.next().as_deref() {
+        Some("ansi") => TraceFormat::Ansi,
+        Some("html") => TraceFormat::Html,
+        Some("json") => TraceFormat::Json,
+        _ => exit_with(USAGE),
+    };
+    let mut set = InstructionSet::conditional();
+    let mut operands = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--extended" => set = InstructionSet::extended(),
+            "--grammar" => {
+                let file = args.next().unwrap_or_else(|| exit_with(USAGE));
+                let grammar = fs::read_to_string(&file)
+                    .map_err(|error| error.to_string())
+                    .and_then(|source| Grammar::from_toml(&source).map_err(|e| e.to_string()))
+                    .unwrap_or_else(|error| exit_with(&format!("error: {}: {}", file, error)));
+                set = InstructionSet::from_grammar(&grammar);
+            }
+            "--lenient" => operands = Some(OperandRule::Lenient),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') || path.is_some() => exit_with(USAGE),
+            _ => path = Some(arg),
+        }
+    }
+    if let Some(operands) = operands {
+        set = set.with_operands(operands);
+    }
+
+    let input = read_input(path.as_deref());
+
+    match render_trace(&set, &input, format) {
+        Ok(trace) => print!("{}", trace),
+        Err(error) => exit_with(&format!("error: {}", error)),
+    }
+}
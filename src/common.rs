@@ -1,15 +1,270 @@
+use crate::error::{AocError, Position};
 use logos::{Lexer, Logos, Source};
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::num::ParseIntError;
+use std::ops::Range;
+use std::str::FromStr;
+use std::{fmt, fs, process};
 
-pub fn parse_int<'a, T: Logos<'a>>(lex: &mut Lexer<'a, T>) -> Option<u32>
+/// Why a token did not lex. Lexers that parse numbers with [`parse_num`] or [`parse_num_with`]
+/// use this as their error type with `#[logos(error = LexError)]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LexError {
+    /// Input that matches none of the lexer's tokens.
+    #[default]
+    Unexpected,
+    /// A number token whose text does not parse as the type it is read into.
+    InvalidNumber {
+        token: String,
+        ty: &'static str,
+        reason: String,
+    },
+}
+
+impl LexError {
+    /// The error for the token at `span` in `input`.
+    pub fn at(self, input: &str, span: Range<usize>) -> AocError {
+        let position = Position::of_offset(input, span.start);
+        match self {
+            LexError::Unexpected => {
+                AocError::lex(position, format!("unexpected {:?}", &input[span]))
+            }
+            error => AocError::lex(position, error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::Unexpected => write!(f, "unexpected token"),
+            LexError::InvalidNumber { token, ty, reason } => {
+                write!(f, "{} is not a valid {}: {}", token, ty, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Integer types that parse in a given radix, for [`parse_num_with`].
+pub trait Radix: FromStr {
+    fn from_str_radix(src: &str, radix: u32) -> Result<Self, ParseIntError>;
+}
+
+macro_rules! impl_radix {
+    ($($ty:ty),*) => {
+        $(impl Radix for $ty {
+            fn from_str_radix(src: &str, radix: u32) -> Result<Self, ParseIntError> {
+                <$ty>::from_str_radix(src, radix)
+            }
+        })*
+    };
+}
+
+impl_radix!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// [`parse_num_with`] flag: a `0x` or `0X` prefix reads the digits as hexadecimal.
+pub const HEX: u8 = 1;
+/// [`parse_num_with`] flag: `_` may separate digits, as in `1_000_000`.
+pub const UNDERSCORES: u8 = 2;
+
+fn invalid_number<T>(token: &str, reason: impl fmt::Display) -> LexError {
+    LexError::InvalidNumber {
+        token: token.to_owned(),
+        ty: std::any::type_name::<T>(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Parses the token as a `T`, such as `#[regex(r"-?\d+", parse_num)] Number(i64)`.
+pub fn parse_num<'a, T, L>(lex: &mut Lexer<'a, L>) -> Result<T, LexError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+    L: Logos<'a>,
+    &'a str: From<<<L as Logos<'a>>::Source as Source>::Slice<'a>>,
+{
+    parse_str(lex.slice().into())
+}
+
+/// Parses part of a token as a `T`, for callbacks that strip punctuation off the number first.
+pub fn parse_str<T>(token: &str) -> Result<T, LexError>
 where
-    &'a str: From<<<T as Logos<'a>>::Source as Source>::Slice<'a>>
+    T: FromStr,
+    T::Err: fmt::Display,
 {
-    Into::<&str>::into(lex.slice()).parse().ok()
+    token
+        .parse()
+        .map_err(|error| invalid_number::<T>(token, error))
 }
 
-pub fn parse_long<'a, T: Logos<'a>>(lex: &mut Lexer<'a, T>) -> Option<u64>
+/// Like [`parse_num`], but also accepts the notations in `FLAGS`, a combination of [`HEX`] and
+/// [`UNDERSCORES`]. The lexer's regex has to match them too. logos cannot read a `{ HEX | .. }`
+/// argument inside its attributes, so lexers call this from a small named callback.
+pub fn parse_num_with<'a, T, L, const FLAGS: u8>(lex: &mut Lexer<'a, L>) -> Result<T, LexError>
 where
-    &'a str: From<<<T as Logos<'a>>::Source as Source>::Slice<'a>>
+    T: Radix,
+    L: Logos<'a>,
+    &'a str: From<<<L as Logos<'a>>::Source as Source>::Slice<'a>>,
 {
-    Into::<&str>::into(lex.slice()).parse().ok()
-}
\ No newline at end of file
+    let token: &str = lex.slice().into();
+    let text = match FLAGS & UNDERSCORES {
+        0 => Cow::Borrowed(token),
+        _ => Cow::Owned(token.replace('_', "")),
+    };
+    let (sign, unsigned) = text.split_at(text.starts_with(['+', '-']) as usize);
+    let hex = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+        .filter(|_| FLAGS & HEX != 0);
+    let parsed = match hex {
+        // `from_str_radix` takes a sign of its own, so one after the prefix would slip through.
+        Some(digits) if digits.starts_with(['+', '-']) => {
+            return Err(invalid_number::<T>(token, "invalid digit found in string"))
+        }
+        Some(digits) if sign.is_empty() => T::from_str_radix(digits, 16),
+        Some(digits) => T::from_str_radix(&format!("{}{}", sign, digits), 16),
+        None => T::from_str_radix(&text, 10),
+    };
+    parsed.map_err(|error| invalid_number::<T>(token, error))
+}
+
+/// Prints `message` to standard error and exits with status 1, for the command-line tools in
+/// `src/bin`.
+pub fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+/// Reads the whole file at `path`, or all of standard input without one, for the command-line
+/// tools. Exits with the error if it cannot be read.
+pub fn read_input(path: Option<&str>) -> String {
+    match path {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map(|_| input)
+        }
+    }
+    .unwrap_or_else(|error| exit_with(&format!("error: {}", error)))
+}
+
+/// The fixed-seed linear congruential generator that randomised tests build their inputs with, so
+/// that every run checks the same cases.
+#[cfg(test)]
+pub struct Lcg(u64);
+
+#[cfg(test)]
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg(seed)
+    }
+
+    /// Advances the generator and returns its whole state.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0
+    }
+
+    /// A number below `bound`, from the high bits of the state, which are the most random.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        (self.next_u64() >> 33) % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Logos, Debug, PartialEq, Eq)]
+    #[logos(error = LexError)]
+    #[logos(skip " ")]
+    enum Decimal {
+        #[regex(r"[+-]?\d+", parse_num)]
+        Number(i8),
+    }
+
+    #[derive(Logos, Debug, PartialEq, Eq)]
+    #[logos(error = LexError)]
+    #[logos(skip " ")]
+    enum Literal {
+        // Lets a sign after the prefix through, for the callback to reject.
+        #[regex(r"[+-]?(0[xX][+-]?[0-9a-fA-F_]+|\d[\d_]*)", parse_literal)]
+        Number(i64),
+    }
+
+    fn parse_literal(lex: &mut Lexer<Literal>) -> Result<i64, LexError> {
+        parse_num_with::<_, _, { HEX | UNDERSCORES }>(lex)
+    }
+
+    #[test]
+    fn test_parse_num() {
+        let tokens: Vec<_> = Decimal::lexer("-12 +7 127").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Ok(Decimal::Number(-12)),
+                Ok(Decimal::Number(7)),
+                Ok(Decimal::Number(127))
+            ]
+        );
+        let mut lexer = Decimal::lexer("1 300 x");
+        assert_eq!(lexer.next(), Some(Ok(Decimal::Number(1))));
+        assert_eq!(
+            lexer.next(),
+            Some(Err(LexError::InvalidNumber {
+                token: "300".to_owned(),
+                ty: "i8",
+                reason: "number too large to fit in target type".to_owned(),
+            }))
+        );
+        let error = lexer.next().unwrap().unwrap_err();
+        assert_eq!(
+            error.at("1 300 x", lexer.span()).to_string(),
+            "invalid input at line 1, column 7: unexpected \"x\""
+        );
+    }
+
+    #[test]
+    fn test_parse_num_with() {
+        let tokens: Vec<_> = Literal::lexer("1_000 0xff -0X1_0 0x7fff_ffff_ffff_ffff").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Ok(Literal::Number(1000)),
+                Ok(Literal::Number(255)),
+                Ok(Literal::Number(-16)),
+                Ok(Literal::Number(i64::MAX))
+            ]
+        );
+        assert_eq!(
+            Literal::lexer("0x8000_0000_0000_0000").next(),
+            Some(Err(LexError::InvalidNumber {
+                token: "0x8000_0000_0000_0000".to_owned(),
+                ty: "i64",
+                reason: "number too large to fit in target type".to_owned(),
+            }))
+        );
+        assert_eq!(
+            Literal::lexer("0x__")
+                .next()
+                .unwrap()
+                .unwrap_err()
+                .to_string(),
+            "0x__ is not a valid i64: cannot parse integer from empty string"
+        );
+        assert_eq!(
+            Literal::lexer("0x-5")
+                .next()
+                .unwrap()
+                .unwrap_err()
+                .to_string(),
+            "0x-5 is not a valid i64: invalid digit found in string"
+        );
+    }
+}
@@ -1,59 +1,1196 @@
+use crate::common::{parse_str, LexError};
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
 use counter::Counter;
-use logos::Logos;
 use itertools::Itertools;
-use crate::common::parse_int;
+use logos::Logos;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::thread;
 
 #[derive(Logos, Debug, PartialEq, Eq)]
+#[logos(skip r"[ \t\r]+")]
 enum Token {
-    #[regex(r"\d+", parse_int)]
-    Number(u32),
+    #[regex(r"-?\d+")]
+    Number,
 
-    #[regex(r"\s*", logos::skip)]
-    Whitespace,
+    /// Stands in for a value a row does not have, in the lists that may differ in length.
+    #[token("-")]
+    Placeholder,
 
     #[token("\n")]
     Newline,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListError {
+    InvalidToken {
+        line: usize,
+        column: usize,
+    },
+    /// A number that does not parse as the location ID type, with the reason from `parse_str`.
+    InvalidNumber {
+        line: usize,
+        column: usize,
+        error: LexError,
+    },
+    WrongColumnCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    UnequalLengths {
+        left: usize,
+        right: usize,
+    },
+    EmptyList,
+    Overflow,
+}
+
+impl fmt::Display for ListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListError::InvalidToken { line, column } => {
+                write!(f, "invalid token at line {}, column {}", line, column)
+            }
+            ListError::InvalidNumber {
+                line,
+                column,
+                error,
+            } => write!(
+                f,
+                "invalid number at line {}, column {}: {}",
+                line, column, error
+            ),
+            ListError::WrongColumnCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} columns at line {} but found {}",
+                expected, line, found
+            ),
+            ListError::UnequalLengths { left, right } => write!(
+                f,
+                "left list has {} values but right list has {}",
+                left, right
+            ),
+            ListError::EmptyList => write!(f, "list is empty"),
+            ListError::Overflow => write!(f, "answer overflowed"),
+        }
+    }
+}
+
+impl Error for ListError {}
+
+impl From<ListError> for AocError {
+    fn from(error: ListError) -> Self {
+        match error {
+            ListError::InvalidToken { line, column } => {
+                AocError::lex(Position::LineColumn { line, column }, "invalid token")
+            }
+            ListError::InvalidNumber {
+                line,
+                column,
+                error,
+            } => AocError::lex(Position::LineColumn { line, column }, error.to_string()),
+            ListError::WrongColumnCount {
+                line,
+                expected,
+                found,
+            } => AocError::structure(
+                Some(Position::Line(line)),
+                format!("expected {} columns but found {}", expected, found),
+            ),
+            ListError::UnequalLengths { .. } | ListError::EmptyList => {
+                AocError::structure(None, error.to_string())
+            }
+            ListError::Overflow => AocError::arithmetic(None, error.to_string()),
+        }
+    }
+}
+
+/// A numeric type that location IDs can be parsed into.
+pub trait LocationId:
+    Copy
+    + Default
+    + Ord
+    + Hash
+    + FromStr<Err = ParseIntError>
+    + fmt::Debug
+    + fmt::Display
+    + Serialize
+    + Send
+{
+    /// The number of bytes a radix sort has to look at.
+    const KEY_BYTES: usize;
 
-fn generate_pairs<'a>(input: &'a str) -> impl Iterator<Item = [u32;2]> + 'a {
-    Token::lexer(input)
-        .tuples::<(_, _, _)>()
-        .map(|(l, r, newline)| {
-            assert_eq!(newline.unwrap(), Token::Newline);
-            if let Ok(Token::Number(left)) = l {
-                if let Ok(Token::Number(right)) = r {
-                    return [left, right]
+    fn distance(self, other: Self) -> u128;
+    fn similarity(self, count: usize) -> Option<i128>;
+
+    /// The `byte`th least significant byte of a key that orders the same way as the value.
+    fn key_byte(self, byte: usize) -> u8;
+}
+
+macro_rules! impl_location_id {
+    ($($t:ty => $unsigned:ty),*) => {
+        $(
+            impl LocationId for $t {
+                const KEY_BYTES: usize = std::mem::size_of::<$t>();
+
+                fn distance(self, other: Self) -> u128 {
+                    self.abs_diff(other) as u128
+                }
+
+                fn similarity(self, count: usize) -> Option<i128> {
+                    i128::try_from(self).ok()?.checked_mul(count as i128)
+                }
+
+                fn key_byte(self, byte: usize) -> u8 {
+                    // Flipping the sign bit of signed values makes their two's complement bits
+                    // order the same way as the values.
+                    let sign_bit = (<$t>::MIN as $unsigned) & (1 << (<$unsigned>::BITS - 1));
+                    (((self as $unsigned) ^ sign_bit) >> (8 * byte)) as u8
                 }
             }
-            panic!("unexpected token {:?}", l);
-        })
+        )*
+    };
 }
 
-fn collect_pairs<'a, L: Extend<u32>, R: Extend<u32>>(left: &mut L, right: &mut R, input: &'a str) {
-    for [l, r] in generate_pairs(input) {
-        left.extend([l]);
-        right.extend([r]);
+impl_location_id!(u32 => u32, u64 => u64, u128 => u128, i64 => u64, i128 => u128);
+
+/// Sorts with a least-significant-byte-first radix sort in O(n · `T::KEY_BYTES`), skipping any
+/// byte that is the same in every value.
+pub fn radix_sort<T: LocationId>(values: &mut [T]) {
+    let mut histograms = vec![[0usize; 256]; T::KEY_BYTES];
+    for value in values.iter() {
+        for (byte, counts) in histograms.iter_mut().enumerate() {
+            counts[value.key_byte(byte) as usize] += 1;
+        }
+    }
+
+    let mut scratch = vec![T::default(); values.len()];
+    let mut sorted_into_scratch = false;
+    for (byte, counts) in histograms.iter().enumerate() {
+        if counts.contains(&values.len()) {
+            continue;
+        }
+        let (source, destination) = if sorted_into_scratch {
+            (&mut scratch[..], &mut values[..])
+        } else {
+            (&mut values[..], &mut scratch[..])
+        };
+
+        let mut offsets = [0usize; 256];
+        for bucket in 1..256 {
+            offsets[bucket] = offsets[bucket - 1] + counts[bucket - 1];
+        }
+        for value in source.iter() {
+            let bucket = value.key_byte(byte) as usize;
+            destination[offsets[bucket]] = *value;
+            offsets[bucket] += 1;
+        }
+        sorted_into_scratch = !sorted_into_scratch;
     }
+    if sorted_into_scratch {
+        values.copy_from_slice(&scratch);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortStrategy {
+    /// `sort_unstable` on each column in turn.
+    Comparison,
+    /// `radix_sort` on each column, with every column on its own scoped thread.
+    ParallelRadix,
+}
+
+impl SortStrategy {
+    /// Below this many rows, spawning threads and allocating radix scratch space costs more than
+    /// the comparison sort it would replace.
+    const PARALLEL_RADIX_MIN_ROWS: usize = 1 << 16;
+
+    pub fn for_rows(rows: usize) -> Self {
+        if rows >= Self::PARALLEL_RADIX_MIN_ROWS {
+            SortStrategy::ParallelRadix
+        } else {
+            SortStrategy::Comparison
+        }
+    }
+}
+
+pub fn sort_columns<T: LocationId>(columns: &mut [Vec<T>], strategy: SortStrategy) {
+    match strategy {
+        SortStrategy::Comparison => {
+            for column in columns.iter_mut() {
+                column.sort_unstable();
+            }
+        }
+        SortStrategy::ParallelRadix => thread::scope(|s| {
+            for column in columns.iter_mut() {
+                s.spawn(|| radix_sort(column));
+            }
+        }),
+    }
+}
+
+/// The narrowest supported location ID type that can hold every number in an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationIdType {
+    U32,
+    U64,
+    U128,
+    I64,
+    I128,
+}
+
+pub fn detect_location_id_type(input: &str) -> LocationIdType {
+    let mut signed = false;
+    let mut min = 0i128;
+    let mut max = 0u128;
+    let mut lexer = Token::lexer(input);
+    while let Some(token) = lexer.next() {
+        if token != Ok(Token::Number) {
+            continue;
+        }
+        let slice = lexer.slice();
+        // Numbers too wide for even the widest type still pick it, and fail to parse later.
+        if slice.starts_with('-') {
+            // Even `-0` needs a signed type, since unsigned ones do not parse a sign.
+            signed = true;
+            min = min.min(parse_str(slice).unwrap_or(i128::MIN));
+        } else {
+            max = max.max(parse_str(slice).unwrap_or(u128::MAX));
+        }
+    }
+
+    if signed {
+        if min >= i64::MIN as i128 && max <= i64::MAX as u128 {
+            LocationIdType::I64
+        } else {
+            LocationIdType::I128
+        }
+    } else if max <= u32::MAX as u128 {
+        LocationIdType::U32
+    } else if max <= u64::MAX as u128 {
+        LocationIdType::U64
+    } else {
+        LocationIdType::U128
+    }
+}
+
+/// Reads whitespace-separated columns, one row per non-blank line, into one list per column.
+/// Every row must have `expected_columns` numbers, or as many as the first row if that is `None`.
+/// The final line does not need a trailing newline.
+pub fn collect_columns<T: LocationId>(
+    input: &str,
+    expected_columns: Option<usize>,
+) -> Result<Vec<Vec<T>>, ListError> {
+    collect_columns_with(input, expected_columns, false)
+}
+
+/// Like `collect_columns`, but a row may leave off trailing values, or write `-` in place of any
+/// value it does not have, so the columns can end up with different lengths.
+pub fn collect_ragged_columns<T: LocationId>(
+    input: &str,
+    columns: usize,
+) -> Result<Vec<Vec<T>>, ListError> {
+    collect_columns_with(input, Some(columns), true)
+}
+
+fn collect_columns_with<T: LocationId>(
+    input: &str,
+    expected_columns: Option<usize>,
+    ragged: bool,
+) -> Result<Vec<Vec<T>>, ListError> {
+    let mut columns: Vec<Vec<T>> = (0..expected_columns.unwrap_or(0)).map(|_| vec![]).collect();
+    let mut expected_columns = expected_columns;
+    let mut lexer = Token::lexer(input);
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut found = 0;
+
+    loop {
+        let row_line = line;
+        let token = lexer.next();
+        match token {
+            Some(Ok(Token::Number)) => {
+                let n = parse_str(lexer.slice()).map_err(|error| ListError::InvalidNumber {
+                    line,
+                    column: lexer.span().start - line_start + 1,
+                    error,
+                })?;
+                if expected_columns.is_none() {
+                    columns.push(vec![]);
+                }
+                if let Some(column) = columns.get_mut(found) {
+                    column.push(n);
+                }
+                found += 1;
+                continue;
+            }
+            Some(Ok(Token::Placeholder)) if ragged => {
+                found += 1;
+                continue;
+            }
+            Some(Ok(Token::Newline)) => {
+                line += 1;
+                line_start = lexer.span().end;
+            }
+            Some(Ok(Token::Placeholder)) | Some(Err(())) => {
+                return Err(ListError::InvalidToken {
+                    line,
+                    column: lexer.span().start - line_start + 1,
+                })
+            }
+            None => {}
+        }
+
+        if found > 0 {
+            let expected = *expected_columns.get_or_insert(found);
+            if found > expected || (found < expected && !ragged) {
+                return Err(ListError::WrongColumnCount {
+                    line: row_line,
+                    expected,
+                    found,
+                });
+            }
+            found = 0;
+        }
+        if token.is_none() {
+            return Ok(columns);
+        }
+    }
+}
+
+/// How the distance between several sorted columns is measured at each rank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMode {
+    /// The sum of the distances between every pair of columns.
+    Pairwise,
+    /// The sum of the distances from each column to the median value at that rank, taking the
+    /// lower median when there is an even number of columns.
+    Median,
+}
+
+/// Sorts every column and sums the distances between values of equal rank.
+pub fn total_distance<T: LocationId>(
+    columns: &mut [Vec<T>],
+    mode: DistanceMode,
+) -> Result<u128, ListError> {
+    let rows = columns.first().map_or(0, Vec::len);
+    sort_columns(columns, SortStrategy::for_rows(rows));
+    let mut rank_values = Vec::with_capacity(columns.len());
+    (0..rows)
+        .try_fold(0u128, |total, rank| {
+            rank_values.clear();
+            rank_values.extend(columns.iter().map(|column| column[rank]));
+            let distance = match mode {
+                DistanceMode::Pairwise => rank_values
+                    .iter()
+                    .tuple_combinations()
+                    .try_fold(0u128, |acc, (a, b)| acc.checked_add(a.distance(*b)))?,
+                DistanceMode::Median => {
+                    rank_values.sort_unstable();
+                    let median = rank_values[(rank_values.len() - 1) / 2];
+                    rank_values
+                        .iter()
+                        .try_fold(0u128, |acc, v| acc.checked_add(v.distance(median)))?
+                }
+            };
+            total.checked_add(distance)
+        })
+        .ok_or(ListError::Overflow)
+}
+
+/// Sums each value of the first column multiplied by the number of times it occurs in every other
+/// column.
+pub fn similarity_score<T: LocationId>(columns: &[Vec<T>]) -> Result<i128, ListError> {
+    let Some((first, others)) = columns.split_first() else {
+        return Ok(0);
+    };
+    let counters: Vec<Counter<T, usize>> = others
+        .iter()
+        .map(|column| column.iter().copied().collect())
+        .collect();
+    first
+        .iter()
+        .try_fold(0i128, |total, n| {
+            let count = counters.iter().try_fold(1usize, |product, counter| {
+                product.checked_mul(*counter.get(n).unwrap_or(&0))
+            })?;
+            total.checked_add(n.similarity(count)?)
+        })
+        .ok_or(ListError::Overflow)
+}
+
+macro_rules! dispatch_on_location_id_type {
+    ($input:expr, $solver:ident $(, $arg:expr)*) => {
+        match detect_location_id_type($input) {
+            LocationIdType::U32 => $solver::<u32>($input $(, $arg)*),
+            LocationIdType::U64 => $solver::<u64>($input $(, $arg)*),
+            LocationIdType::U128 => $solver::<u128>($input $(, $arg)*),
+            LocationIdType::I64 => $solver::<i64>($input $(, $arg)*),
+            LocationIdType::I128 => $solver::<i128>($input $(, $arg)*),
+        }
+    };
 }
 
 #[aoc(day1, part1)]
-pub fn part1(input: &str) -> u32 {
-    let mut left = vec![];
-    let mut right = vec![];
-    collect_pairs(&mut left, &mut right, input);
+pub fn part1(input: &str) -> Result<u128, AocError> {
+    Ok(dispatch_on_location_id_type!(input, part1_as)?)
+}
+
+pub fn part1_as<T: LocationId>(input: &str) -> Result<u128, ListError> {
+    total_distance(
+        &mut collect_columns::<T>(input, Some(2))?,
+        DistanceMode::Pairwise,
+    )
+}
+
+#[aoc(day1, part2)]
+pub fn part2(input: &str) -> Result<i128, AocError> {
+    Ok(dispatch_on_location_id_type!(input, part2_as)?)
+}
+
+pub fn part2_as<T: LocationId>(input: &str) -> Result<i128, ListError> {
+    similarity_score(&collect_columns::<T>(input, Some(2))?)
+}
+
+/// Distance between any number of columns, with the ID type picked from the input.
+pub fn reconcile_distance(input: &str, mode: DistanceMode) -> Result<u128, ListError> {
+    dispatch_on_location_id_type!(input, reconcile_distance_as, mode)
+}
+
+pub fn reconcile_distance_as<T: LocationId>(
+    input: &str,
+    mode: DistanceMode,
+) -> Result<u128, ListError> {
+    total_distance(&mut collect_columns::<T>(input, None)?, mode)
+}
+
+/// Similarity across any number of columns, with the ID type picked from the input.
+pub fn reconcile_similarity(input: &str) -> Result<i128, ListError> {
+    dispatch_on_location_id_type!(input, reconcile_similarity_as)
+}
+
+pub fn reconcile_similarity_as<T: LocationId>(input: &str) -> Result<i128, ListError> {
+    similarity_score(&collect_columns::<T>(input, None)?)
+}
+
+/// The cost of pairing two values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Absolute,
+    Squared,
+}
+
+impl Metric {
+    fn cost<T: LocationId>(self, a: T, b: T) -> Option<u128> {
+        let distance = a.distance(b);
+        match self {
+            Metric::Absolute => Some(distance),
+            Metric::Squared => distance.checked_mul(distance),
+        }
+    }
+}
+
+/// What to do with the values of the longer list that have no partner in the shorter one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmatched {
+    /// Fail with `ListError::UnequalLengths`.
+    Error,
+    /// Pair the lists smallest to smallest and charge a fixed cost for each leftover value.
+    Penalty(u128),
+    /// Leave out whichever values of the longer list give the cheapest matching.
+    OptimalAssignment,
+}
+
+/// Sorts both lists and sums the cost of pairing them up, smallest to smallest. Both metrics are
+/// convex in the difference, so when the lists are the same length this is also the cheapest
+/// way to pair them.
+pub fn paired_distance<T: LocationId>(
+    left: &mut [T],
+    right: &mut [T],
+    metric: Metric,
+    unmatched: Unmatched,
+) -> Result<u128, ListError> {
     left.sort_unstable();
     right.sort_unstable();
-    left.iter().zip(right.iter()).map(|(l, r)| l.abs_diff(*r)).sum()
+    let leftovers = left.len().abs_diff(right.len());
+    let penalty = match unmatched {
+        _ if leftovers == 0 => 0,
+        Unmatched::Error => {
+            return Err(ListError::UnequalLengths {
+                left: left.len(),
+                right: right.len(),
+            })
+        }
+        Unmatched::Penalty(penalty) => penalty
+            .checked_mul(leftovers as u128)
+            .ok_or(ListError::Overflow)?,
+        Unmatched::OptimalAssignment => return min_cost_matching(left, right, metric),
+    };
+    left.iter()
+        .zip(right.iter())
+        .try_fold(penalty, |total, (l, r)| {
+            total.checked_add(metric.cost(*l, *r)?)
+        })
+        .ok_or(ListError::Overflow)
 }
 
-#[aoc(day1, part2)]
-pub fn part2(input: &str) -> u32 {
-    let mut left = vec![];
-    let mut right: Counter<u32, u32> = Counter::new();
-    collect_pairs(&mut left, &mut right, input);
-    left.into_iter()
-        .map(|n| *right.get(&n).unwrap_or(&0) * n)
-        .sum()
-}
\ No newline at end of file
+/// Finds the cheapest way to pair every value of the shorter list with a distinct value of the
+/// longer one. With a convex metric some cheapest matching never crosses itself once both lists
+/// are sorted, so a dynamic programme over sorted prefixes finds it in O(n · (m - n + 1)).
+pub fn min_cost_matching<T: LocationId>(
+    left: &mut [T],
+    right: &mut [T],
+    metric: Metric,
+) -> Result<u128, ListError> {
+    left.sort_unstable();
+    right.sort_unstable();
+    let (short, long) = if left.len() <= right.len() {
+        (&*left, &*right)
+    } else {
+        (&*right, &*left)
+    };
+    let slack = long.len() - short.len();
+
+    // cheapest[k] is the cost of matching the short prefix so far against the long prefix that
+    // skips exactly k values.
+    let mut cheapest = vec![Some(0u128); slack + 1];
+    for (i, &s) in short.iter().enumerate() {
+        let mut skipping_fewer: Option<u128> = None;
+        for (k, entry) in cheapest.iter_mut().enumerate() {
+            let matched = entry.and_then(|cost| cost.checked_add(metric.cost(s, long[i + k])?));
+            *entry = match (skipping_fewer, matched) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            skipping_fewer = *entry;
+        }
+    }
+    cheapest
+        .into_iter()
+        .flatten()
+        .min()
+        .ok_or(ListError::Overflow)
+}
+
+/// The 1-dimensional earth mover's distance between the two lists as distributions of equal
+/// total mass: the area between their cumulative distribution functions.
+pub fn earth_movers_distance<T: LocationId>(
+    left: &mut [T],
+    right: &mut [T],
+) -> Result<f64, ListError> {
+    if left.is_empty() || right.is_empty() {
+        return Err(ListError::EmptyList);
+    }
+    left.sort_unstable();
+    right.sort_unstable();
+
+    // Scaling the left distribution by |right| and the right one by |left| keeps every CDF
+    // difference an integer until the final division.
+    let (n, m) = (left.len() as i128, right.len() as i128);
+    let points = left
+        .iter()
+        .merge(right.iter())
+        .dedup()
+        .copied()
+        .collect_vec();
+    let (mut left_seen, mut right_seen) = (0, 0);
+    let mut scaled_area = 0u128;
+    for (&x, &next) in points.iter().tuple_windows() {
+        left_seen += left[left_seen..].iter().take_while(|v| **v <= x).count();
+        right_seen += right[right_seen..].iter().take_while(|v| **v <= x).count();
+        let height = (left_seen as i128 * m - right_seen as i128 * n).unsigned_abs();
+        scaled_area = height
+            .checked_mul(x.distance(next))
+            .and_then(|area| scaled_area.checked_add(area))
+            .ok_or(ListError::Overflow)?;
+    }
+    Ok(scaled_area as f64 / (n * m) as f64)
+}
+
+fn list_distance_as<T: LocationId>(
+    input: &str,
+    metric: Metric,
+    unmatched: Unmatched,
+) -> Result<u128, ListError> {
+    let mut columns = collect_ragged_columns::<T>(input, 2)?;
+    let (left, right) = columns.split_at_mut(1);
+    paired_distance(&mut left[0], &mut right[0], metric, unmatched)
+}
+
+/// Distance between two lists that may have different lengths, with the ID type picked from the
+/// input. Rows with a single value only add to the left list, and rows like `- 5` only to the
+/// right one.
+pub fn list_distance(input: &str, metric: Metric, unmatched: Unmatched) -> Result<u128, ListError> {
+    dispatch_on_location_id_type!(input, list_distance_as, metric, unmatched)
+}
+
+fn list_earth_movers_distance_as<T: LocationId>(input: &str) -> Result<f64, ListError> {
+    let mut columns = collect_ragged_columns::<T>(input, 2)?;
+    let (left, right) = columns.split_at_mut(1);
+    earth_movers_distance(&mut left[0], &mut right[0])
+}
+
+/// Earth mover's distance between two lists that may have different lengths, with the ID type
+/// picked from the input.
+pub fn list_earth_movers_distance(input: &str) -> Result<f64, ListError> {
+    dispatch_on_location_id_type!(input, list_earth_movers_distance_as)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Distance,
+    Similarity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DistanceRow<T> {
+    pub left: T,
+    pub right: T,
+    pub distance: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SimilarityRow<T> {
+    pub left: T,
+    pub count: usize,
+    pub contribution: i128,
+}
+
+/// Every sorted pair with its distance and every left value with its similarity contribution,
+/// along with the totals that answer both parts of the puzzle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingReport<T> {
+    pub distances: Vec<DistanceRow<T>>,
+    pub total_distance: u128,
+    pub similarities: Vec<SimilarityRow<T>>,
+    pub similarity_score: i128,
+}
+
+#[derive(Serialize)]
+struct ReportSection<'a, R, S> {
+    rows: &'a [R],
+    total: S,
+}
+
+pub fn pairing_report<T: LocationId>(input: &str) -> Result<PairingReport<T>, ListError> {
+    let columns = collect_columns::<T>(input, Some(2))?;
+    let [left, right] = <[Vec<T>; 2]>::try_from(columns).unwrap();
+
+    let counts: Counter<T, usize> = right.iter().copied().collect();
+    let similarities = left
+        .iter()
+        .map(|&n| {
+            let count = *counts.get(&n).unwrap_or(&0);
+            Some(SimilarityRow {
+                left: n,
+                count,
+                contribution: n.similarity(count)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(ListError::Overflow)?;
+
+    let distances = left
+        .into_iter()
+        .sorted_unstable()
+        .zip(right.into_iter().sorted_unstable())
+        .map(|(left, right)| DistanceRow {
+            left,
+            right,
+            distance: left.distance(right),
+        })
+        .collect_vec();
+
+    Ok(PairingReport {
+        total_distance: distances
+            .iter()
+            .try_fold(0u128, |total, row| total.checked_add(row.distance))
+            .ok_or(ListError::Overflow)?,
+        distances,
+        similarity_score: similarities
+            .iter()
+            .try_fold(0i128, |total, row| total.checked_add(row.contribution))
+            .ok_or(ListError::Overflow)?,
+        similarities,
+    })
+}
+
+impl<T: LocationId> PairingReport<T> {
+    /// Writes one row per pair or left value, followed by a `total` line.
+    pub fn write_csv<W: Write>(&self, kind: ReportKind, mut out: W) -> io::Result<()> {
+        match kind {
+            ReportKind::Distance => {
+                writeln!(out, "left,right,distance")?;
+                for row in &self.distances {
+                    writeln!(out, "{},{},{}", row.left, row.right, row.distance)?;
+                }
+                writeln!(out, "total,,{}", self.total_distance)
+            }
+            ReportKind::Similarity => {
+                writeln!(out, "left,count,contribution")?;
+                for row in &self.similarities {
+                    writeln!(out, "{},{},{}", row.left, row.count, row.contribution)?;
+                }
+                writeln!(out, "total,,{}", self.similarity_score)
+            }
+        }
+    }
+
+    /// Writes an object with a `rows` array and a `total`.
+    pub fn write_json<W: Write>(&self, kind: ReportKind, mut out: W) -> io::Result<()> {
+        match kind {
+            ReportKind::Distance => serde_json::to_writer_pretty(
+                &mut out,
+                &ReportSection {
+                    rows: &self.distances,
+                    total: self.total_distance,
+                },
+            ),
+            ReportKind::Similarity => serde_json::to_writer_pretty(
+                &mut out,
+                &ReportSection {
+                    rows: &self.similarities,
+                    total: self.similarity_score,
+                },
+            ),
+        }?;
+        writeln!(out)
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        kind: ReportKind,
+        format: ReportFormat,
+        out: W,
+    ) -> io::Result<()> {
+        match format {
+            ReportFormat::Csv => self.write_csv(kind, out),
+            ReportFormat::Json => self.write_json(kind, out),
+        }
+    }
+}
+
+fn render_report_as<T: LocationId>(
+    input: &str,
+    kind: ReportKind,
+    format: ReportFormat,
+) -> Result<String, ListError> {
+    let mut out = vec![];
+    pairing_report::<T>(input)?
+        .write(kind, format, &mut out)
+        .expect("writing to a Vec cannot fail");
+    Ok(String::from_utf8(out).unwrap())
+}
+
+/// Renders a pairing report, with the ID type picked from the input.
+pub fn render_report(
+    input: &str,
+    kind: ReportKind,
+    format: ReportFormat,
+) -> Result<String, ListError> {
+    dispatch_on_location_id_type!(input, render_report_as, kind, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Lcg;
+
+    const EXAMPLE: &str = "3   4
+4   3
+2   5
+1   3
+3   9
+3   3
+";
+
+    #[test]
+    fn test_part1() {
+        assert_eq!(part1(EXAMPLE), Ok(11));
+    }
+
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(EXAMPLE), Ok(31));
+    }
+
+    #[test]
+    fn test_line_endings_and_separators() {
+        let input = "3\t4\r\n4   3\r\n2 5\n\n1\t 3\r\n3   9\n3   3";
+        assert_eq!(part1(input), Ok(11));
+        assert_eq!(part2(input), Ok(31));
+        assert_eq!(part1("3   4  \n4   3 \t"), Ok(0));
+    }
+
+    #[test]
+    fn test_invalid_token() {
+        assert_eq!(
+            part1("3   4\n4   x3\n"),
+            Err(ListError::InvalidToken { line: 2, column: 5 }.into())
+        );
+    }
+
+    #[test]
+    fn test_detect_location_id_type() {
+        assert_eq!(detect_location_id_type(EXAMPLE), LocationIdType::U32);
+        assert_eq!(detect_location_id_type("1 4294967296"), LocationIdType::U64);
+        assert_eq!(
+            detect_location_id_type("1 18446744073709551616"),
+            LocationIdType::U128
+        );
+        assert_eq!(
+            detect_location_id_type("-1 4294967296"),
+            LocationIdType::I64
+        );
+        assert_eq!(
+            detect_location_id_type("-1 9223372036854775808"),
+            LocationIdType::I128
+        );
+        assert_eq!(detect_location_id_type("-0   5"), LocationIdType::I64);
+        assert_eq!(part1("-0   5\n3   4\n"), Ok(4 + 2));
+    }
+
+    #[test]
+    fn test_wide_and_signed_ids() {
+        let input = "-3   4\n4   -5\n";
+        assert_eq!(part1(input), Ok(2));
+        assert_eq!(part2(input), Ok(4));
+        assert_eq!(part2("-3   -3\n2   -3\n"), Ok(-6));
+
+        let big = u64::MAX as u128 + 1;
+        let input = format!("{}   {}\n{}   1\n", big, big + 5, big);
+        assert_eq!(part1(&input), Ok(big - 1 + 5));
+        assert_eq!(
+            part1_as::<u64>(&input).unwrap_err().to_string(),
+            "invalid number at line 1, column 1: 18446744073709551616 is not a valid u64: \
+             number too large to fit in target type"
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let input = format!("{}   {}\n", u128::MAX, u128::MAX);
+        assert_eq!(part2(&input), Err(ListError::Overflow.into()));
+        assert_eq!(
+            part2(&input).unwrap_err().to_string(),
+            "arithmetic error: answer overflowed"
+        );
+        assert_eq!(
+            part1(
+                "1   2
+3
+"
+            )
+            .unwrap_err()
+            .to_string(),
+            "malformed input at line 2: expected 2 columns but found 1"
+        );
+    }
+
+    #[test]
+    fn test_two_columns_match_puzzle() {
+        assert_eq!(reconcile_distance(EXAMPLE, DistanceMode::Pairwise), Ok(11));
+        assert_eq!(reconcile_distance(EXAMPLE, DistanceMode::Median), Ok(11));
+        assert_eq!(reconcile_similarity(EXAMPLE), Ok(31));
+    }
+
+    #[test]
+    fn test_three_columns() {
+        let input = "1 5 3\n4 2 4\n9 4 4\n";
+        // Sorted ranks: [1, 2, 3], [4, 4, 4], [9, 5, 4].
+        assert_eq!(
+            reconcile_distance(input, DistanceMode::Pairwise),
+            Ok(4 + 10)
+        );
+        assert_eq!(reconcile_distance(input, DistanceMode::Median), Ok(2 + 5));
+        // Only 4 occurs in every column: once in the second and twice in the third.
+        assert_eq!(reconcile_similarity(input), Ok(4 * 2));
+    }
+
+    #[test]
+    fn test_column_count_is_inferred() {
+        assert_eq!(
+            reconcile_similarity("1 2 3\n4 5\n"),
+            Err(ListError::WrongColumnCount {
+                line: 2,
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(reconcile_distance("", DistanceMode::Median), Ok(0));
+        assert_eq!(reconcile_similarity("\n"), Ok(0));
+    }
+
+    #[test]
+    fn test_pairing_report() {
+        let report = pairing_report::<u32>(EXAMPLE).unwrap();
+        assert_eq!(report.total_distance, 11);
+        assert_eq!(report.similarity_score, 31);
+        assert_eq!(
+            report.distances[0],
+            DistanceRow {
+                left: 1,
+                right: 3,
+                distance: 2
+            }
+        );
+        assert_eq!(
+            report.similarities[0],
+            SimilarityRow {
+                left: 3,
+                count: 3,
+                contribution: 9
+            }
+        );
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let csv = render_report("3   4\n1   3\n", ReportKind::Distance, ReportFormat::Csv);
+        assert_eq!(
+            csv.unwrap(),
+            "left,right,distance\n1,3,2\n3,4,1\ntotal,,3\n"
+        );
+        let csv = render_report("3   4\n1   3\n", ReportKind::Similarity, ReportFormat::Csv);
+        assert_eq!(
+            csv.unwrap(),
+            "left,count,contribution\n3,1,3\n1,0,0\ntotal,,3\n"
+        );
+    }
+
+    #[test]
+    fn test_report_json() {
+        let json = render_report(EXAMPLE, ReportKind::Similarity, ReportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["total"], 31);
+        assert_eq!(value["rows"].as_array().unwrap().len(), 6);
+        assert_eq!(value["rows"][1]["left"], 4);
+        assert_eq!(value["rows"][1]["count"], 1);
+        assert_eq!(value["rows"][1]["contribution"], 4);
+    }
+
+    #[test]
+    fn test_radix_sort() {
+        let mut rng = Lcg::new(7);
+        let mut next = || rng.next_u64();
+
+        let mut unsigned: Vec<u32> = (0..1000).map(|_| (next() >> 40) as u32).collect();
+        let mut expected = unsigned.clone();
+        expected.sort_unstable();
+        radix_sort(&mut unsigned);
+        assert_eq!(unsigned, expected);
+
+        let mut signed: Vec<i64> = (0..1000).map(|_| next() as i64 >> 20).collect();
+        signed.extend([i64::MIN, i64::MAX, 0, -1]);
+        let mut expected = signed.clone();
+        expected.sort_unstable();
+        radix_sort(&mut signed);
+        assert_eq!(signed, expected);
+
+        let mut wide: Vec<i128> = (0..1000).map(|_| (next() as i128) << 60).collect();
+        wide.extend([i128::MIN, i128::MAX, -1]);
+        let mut expected = wide.clone();
+        expected.sort_unstable();
+        radix_sort(&mut wide);
+        assert_eq!(wide, expected);
+    }
+
+    #[test]
+    fn test_sort_strategies_agree() {
+        let input: String = (0..2000u32)
+            .map(|i| {
+                format!(
+                    "{}   {}\n",
+                    i.wrapping_mul(2654435761) % 100_000,
+                    i * 7 % 1013
+                )
+            })
+            .collect();
+        let mut comparison = collect_columns::<u32>(&input, Some(2)).unwrap();
+        let mut radix = comparison.clone();
+        sort_columns(&mut comparison, SortStrategy::Comparison);
+        sort_columns(&mut radix, SortStrategy::ParallelRadix);
+        assert_eq!(comparison, radix);
+    }
+
+    /// Tries every way of pairing each value of the shorter list with a distinct value of the
+    /// longer one.
+    fn brute_force_matching(short: &[i64], long: &[i64], metric: Metric) -> u128 {
+        long.iter()
+            .permutations(short.len())
+            .map(|chosen| {
+                short
+                    .iter()
+                    .zip(chosen)
+                    .map(|(a, b)| metric.cost(*a, *b).unwrap())
+                    .sum()
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn brute_force_earth_movers(left: &[i64], right: &[i64]) -> f64 {
+        let cdf = |list: &[i64], x: i64| {
+            list.iter().filter(|v| **v <= x).count() as f64 / list.len() as f64
+        };
+        let (low, high) = left
+            .iter()
+            .chain(right)
+            .copied()
+            .minmax()
+            .into_option()
+            .unwrap();
+        (low..high)
+            .map(|x| (cdf(left, x) - cdf(right, x)).abs())
+            .sum()
+    }
+
+    fn random_lists(rng: &mut Lcg) -> (Vec<i64>, Vec<i64>) {
+        let left_len = rng.below(6) as usize;
+        let right_len = rng.below(6) as usize;
+        let left = (0..left_len).map(|_| rng.below(21) as i64 - 10).collect();
+        let right = (0..right_len).map(|_| rng.below(21) as i64 - 10).collect();
+        (left, right)
+    }
+
+    #[test]
+    fn test_metrics_match_brute_force() {
+        let mut rng = Lcg::new(35);
+        for _ in 0..300 {
+            let (mut left, mut right) = random_lists(&mut rng);
+            for metric in [Metric::Absolute, Metric::Squared] {
+                let (short, long) = if left.len() <= right.len() {
+                    (&left, &right)
+                } else {
+                    (&right, &left)
+                };
+                let expected = brute_force_matching(short, long, metric);
+                assert_eq!(
+                    min_cost_matching(&mut left.clone(), &mut right.clone(), metric),
+                    Ok(expected),
+                    "{:?} {:?} {:?}",
+                    left,
+                    right,
+                    metric
+                );
+                let unmatched = if left.len() == right.len() {
+                    Unmatched::Error
+                } else {
+                    Unmatched::OptimalAssignment
+                };
+                assert_eq!(
+                    paired_distance(&mut left, &mut right, metric, unmatched),
+                    Ok(expected)
+                );
+            }
+            if !left.is_empty() && !right.is_empty() {
+                let emd = earth_movers_distance(&mut left, &mut right).unwrap();
+                let expected = brute_force_earth_movers(&left, &right);
+                assert!((emd - expected).abs() < 1e-9, "{:?} {:?}", left, right);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unmatched_policies() {
+        let input = "3   4\n4   3\n2   5\n1   3\n3   9\n3   3\n7\n";
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::Error),
+            Err(ListError::UnequalLengths { left: 7, right: 6 })
+        );
+        // The sorted pairing leaves the 7 over, so the penalty is added to the part 1 answer.
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::Penalty(100)),
+            Ok(11 + 100)
+        );
+        // Dropping the 1 instead pairs 2-3, 3-3, 3-3, 3-4, 4-5 and 7-9.
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::OptimalAssignment),
+            Ok(5)
+        );
+        // Sorted pairs differ by 2, 1, 0, 1, 2 and 5.
+        assert_eq!(
+            list_distance(EXAMPLE, Metric::Squared, Unmatched::Error),
+            Ok(35)
+        );
+        assert_eq!(list_earth_movers_distance(EXAMPLE), Ok(11.0 / 6.0));
+        assert_eq!(list_earth_movers_distance("1\n"), Err(ListError::EmptyList));
+        assert_eq!(
+            list_earth_movers_distance("- 1\n"),
+            Err(ListError::EmptyList)
+        );
+    }
+
+    #[test]
+    fn test_right_list_longer() {
+        let input = "3   4\n4   3\n2   5\n1   3\n3   9\n-   7\n3   3\n";
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::Error),
+            Err(ListError::UnequalLengths { left: 6, right: 7 })
+        );
+        // The sorted pairing is 1-3, 2-3, 3-3, 3-4, 3-5 and 4-7, leaving the 9 over.
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::Penalty(100)),
+            Ok(9 + 100)
+        );
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::OptimalAssignment),
+            Ok(9)
+        );
+        // Sorted, the -20 pairs with the 1 and the 9 is left over. Leaving out the -20 instead
+        // pairs 1-3, 2-3, 3-3, 3-4, 3-5 and 4-9.
+        let input = "3   4\n4   3\n2   5\n1   3\n3   9\n-   -20\n3   3\n";
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::Penalty(100)),
+            Ok(24 + 100)
+        );
+        assert_eq!(
+            list_distance(input, Metric::Absolute, Unmatched::OptimalAssignment),
+            Ok(11)
+        );
+        // A placeholder only makes sense where the lists may differ in length.
+        assert_eq!(
+            part1("3   4\n-   3\n"),
+            Err(ListError::InvalidToken { line: 2, column: 1 }.into())
+        );
+    }
+
+    #[test]
+    fn test_wrong_column_count() {
+        assert_eq!(
+            part1("3   4\n4\n2   5\n"),
+            Err(ListError::WrongColumnCount {
+                line: 2,
+                expected: 2,
+                found: 1
+            }
+            .into())
+        );
+        assert_eq!(
+            part2("3   4\n2   5   6"),
+            Err(ListError::WrongColumnCount {
+                line: 2,
+                expected: 2,
+                found: 3
+            }
+            .into())
+        );
+    }
+}
@@ -1,12 +1,22 @@
-use crate::common::parse_int;
+use crate::common::{parse_num, LexError};
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
 use derive_more::TryInto;
 use itertools::Itertools;
 use logos::Logos;
+use memchr::memrchr;
+use serde::Deserialize;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
 
 #[derive(Logos, Debug, PartialEq, Eq, TryInto)]
+#[logos(error = LexError)]
 enum Token {
-    #[regex(r"\d+", parse_int)]
+    #[regex(r"\d+", parse_num)]
     Number(u32),
 
     #[token(" ", logos::skip)]
@@ -16,86 +26,1043 @@ enum Token {
     Newline,
 }
 
-fn iterate_reports<'a, T, H>(input: &'a str, handler: H) -> impl Iterator<Item = T> + 'a
-where
-    H: Fn(&Vec<u32>) -> T + 'static,
-{
-    let mut report: Vec<u32> = Vec::with_capacity(20);
-    Token::lexer(input).batching(move |it| loop {
-        match it.next() {
-            Some(Ok(Token::Number(n))) => report.push(n),
-            Some(Ok(Token::Newline)) => {
-                let result = handler(&report);
-                report.clear();
-                return Some(result);
-            }
-            None => return None,
-            _ => unreachable!(),
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Ascending,
+    Descending,
+    Either,
+}
+
+/// Why a report is unsafe: the step from level `index` (`from`) to level `index + 1` (`to`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The step goes the other way to the policy's direction or to the steps before it.
+    DirectionFlip {
+        index: usize,
+        from: u32,
+        to: u32,
+    },
+    StepTooSmall {
+        index: usize,
+        from: u32,
+        to: u32,
+    },
+    StepTooLarge {
+        index: usize,
+        from: u32,
+        to: u32,
+    },
+    ZeroStep {
+        index: usize,
+        from: u32,
+        to: u32,
+    },
+}
+
+impl Violation {
+    pub fn index(&self) -> usize {
+        match *self {
+            Violation::DirectionFlip { index, .. }
+            | Violation::StepTooSmall { index, .. }
+            | Violation::StepTooLarge { index, .. }
+            | Violation::ZeroStep { index, .. } => index,
         }
-    })
+    }
+
+    pub fn cause(&self) -> &'static str {
+        match self {
+            Violation::DirectionFlip { .. } => "direction flip",
+            Violation::StepTooSmall { .. } => "step too small",
+            Violation::StepTooLarge { .. } => "step too large",
+            Violation::ZeroStep { .. } => "zero step",
+        }
+    }
 }
 
-fn return_first_invalid_index<'a, I>(report: I) -> Option<usize>
-where
-    I: Iterator<Item = &'a u32>,
-{
-    let mut is_ascending: Option<bool> = None;
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Violation::DirectionFlip { index, from, to }
+        | Violation::StepTooSmall { index, from, to }
+        | Violation::StepTooLarge { index, from, to }
+        | Violation::ZeroStep { index, from, to }) = *self;
+        write!(
+            f,
+            "{} at index {} ({} -> {})",
+            self.cause(),
+            index,
+            from,
+            to
+        )
+    }
+}
+
+/// Renders a report with brackets around the two levels of the violating step.
+pub fn mark_violation(report: &[u32], violation: &Violation) -> String {
     report
-        .tuple_windows()
+        .iter()
         .enumerate()
-        .filter_map(|(i, (l, r))| {
-            let rhs_greater = *r > *l;
-            if let Some(last_iteration_was_ascending) = is_ascending {
-                if last_iteration_was_ascending != rhs_greater {
-                    return Some(i);
-                }
-            }
-            is_ascending = Some(rhs_greater);
-
-            let abs_diff = r.abs_diff(*l);
-            if abs_diff == 1 || abs_diff == 2 || abs_diff == 3 {
-                None
+        .map(|(i, level)| {
+            if i == violation.index() {
+                format!("[{}", level)
+            } else if i == violation.index() + 1 {
+                format!("{}]", level)
             } else {
-                Some(i)
+                level.to_string()
             }
         })
-        .next()
+        .join(" ")
+}
+
+/// A single change to a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Remove {
+        index: usize,
+        value: u32,
+    },
+    Replace {
+        index: usize,
+        value: u32,
+        with: u32,
+    },
+    /// Insert `value` so that it ends up at `index`, before the level currently there.
+    Insert {
+        index: usize,
+        value: u32,
+    },
+}
+
+impl Edit {
+    pub fn apply(&self, report: &[u32]) -> Vec<u32> {
+        let mut edited = report.to_vec();
+        match *self {
+            Edit::Remove { index, .. } => {
+                edited.remove(index);
+            }
+            Edit::Replace { index, with, .. } => edited[index] = with,
+            Edit::Insert { index, value } => edited.insert(index, value),
+        }
+        edited
+    }
 }
 
-fn skip_index<'a, I, T>(index: &'a usize, iterator: I) -> impl Iterator<Item = T> + 'a
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edit::Remove { index, value } => write!(f, "remove index {} (value {})", index, value),
+            Edit::Replace { index, value, with } => {
+                write!(f, "replace index {} (value {}) with {}", index, value, with)
+            }
+            Edit::Insert { index, value } => write!(f, "insert {} at index {}", value, index),
+        }
+    }
+}
+
+/// How much a report has to change to become safe. Unsafe reports are classified by the least
+/// invasive kind of single edit that fixes them, in the order removal, replacement, insertion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Repairability {
+    Safe,
+    Removal,
+    Replacement,
+    Insertion,
+    Unrepairable,
+}
+
+/// What makes a report safe. Every step between neighbouring levels that is not zero must move
+/// in the allowed direction by between `min_step` and `max_step`; a zero step is only allowed
+/// when `allow_equal` is set, and never decides the direction of an `Either` report.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafetyPolicy {
+    pub min_step: u32,
+    pub max_step: u32,
+    pub allow_equal: bool,
+    pub direction: Direction,
+}
+
+impl Default for SafetyPolicy {
+    /// The puzzle's policy: strictly ascending or descending by 1 to 3.
+    fn default() -> Self {
+        SafetyPolicy {
+            min_step: 1,
+            max_step: 3,
+            allow_equal: false,
+            direction: Direction::Either,
+        }
+    }
+}
+
+impl SafetyPolicy {
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    pub fn step_range(&self) -> RangeInclusive<u32> {
+        self.min_step..=self.max_step
+    }
+
+    /// The first step that breaks the policy, if any.
+    pub fn first_violation(&self, report: &[u32]) -> Option<Violation> {
+        let mut is_ascending: Option<bool> = match self.direction {
+            Direction::Ascending => Some(true),
+            Direction::Descending => Some(false),
+            Direction::Either => None,
+        };
+        report
+            .iter()
+            .copied()
+            .tuple_windows()
+            .enumerate()
+            .find_map(|(index, (from, to))| {
+                if from == to {
+                    return (!self.allow_equal).then_some(Violation::ZeroStep { index, from, to });
+                }
+
+                let rhs_greater = to > from;
+                if let Some(last_iteration_was_ascending) = is_ascending {
+                    if last_iteration_was_ascending != rhs_greater {
+                        return Some(Violation::DirectionFlip { index, from, to });
+                    }
+                }
+                is_ascending = Some(rhs_greater);
+
+                let step = to.abs_diff(from);
+                if step < self.min_step {
+                    Some(Violation::StepTooSmall { index, from, to })
+                } else if step > self.max_step {
+                    Some(Violation::StepTooLarge { index, from, to })
+                } else {
+                    None
+                }
+            })
+    }
+
+    pub fn is_safe(&self, report: &[u32]) -> bool {
+        self.first_violation(report).is_none()
+    }
+
+    fn is_valid_step(&self, from: u32, to: u32, ascending: bool) -> bool {
+        if from == to {
+            self.allow_equal
+        } else {
+            (to > from) == ascending && self.step_range().contains(&from.abs_diff(to))
+        }
+    }
+
+    /// The fewest levels that have to be removed to make the report safe, found from the longest
+    /// safe subsequence in each allowed direction. `longest[i]` is the length of the longest safe
+    /// subsequence that ends with level `i`.
+    pub fn minimum_removals(&self, report: &[u32]) -> usize {
+        let directions: &[bool] = match self.direction {
+            Direction::Ascending => &[true],
+            Direction::Descending => &[false],
+            Direction::Either => &[true, false],
+        };
+        let mut longest = vec![0usize; report.len()];
+        let longest_safe = directions
+            .iter()
+            .map(|&ascending| {
+                for i in 0..report.len() {
+                    longest[i] = 1
+                        + (0..i)
+                            .filter(|&j| self.is_valid_step(report[j], report[i], ascending))
+                            .map(|j| longest[j])
+                            .max()
+                            .unwrap_or(0);
+                }
+                longest.iter().copied().max().unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0);
+        report.len() - longest_safe
+    }
+
+    /// Every level that is a valid step away from `level`, plus `level` itself.
+    fn values_near(&self, level: u32) -> impl Iterator<Item = u32> + '_ {
+        self.step_range()
+            .flat_map(move |step| [level.checked_sub(step), level.checked_add(step)])
+            .flatten()
+            .chain([level])
+    }
+
+    /// The value closest to `target` that makes the report safe when written at `index` by
+    /// `edit`. Only values a valid step away from a neighbour can work, so those are the only
+    /// candidates.
+    fn best_value_at<E>(
+        &self,
+        report: &[u32],
+        neighbours: &[u32],
+        target: u32,
+        edit: E,
+    ) -> Option<u32>
+    where
+        E: Fn(u32) -> Edit,
+    {
+        neighbours
+            .iter()
+            .flat_map(|&neighbour| self.values_near(neighbour))
+            .filter(|&value| self.is_safe(&edit(value).apply(report)))
+            .min_by_key(|&value| (value.abs_diff(target), value))
+    }
+
+    /// Every single edit that makes an unsafe report safe: each removal that works, and for each
+    /// index the replacement or insertion that changes the report the least.
+    pub fn repairs(&self, report: &[u32]) -> Vec<Edit> {
+        if self.is_safe(report) {
+            return vec![];
+        }
+        let removals = (0..report.len())
+            .map(|index| Edit::Remove {
+                index,
+                value: report[index],
+            })
+            .filter(|edit| self.is_safe(&edit.apply(report)));
+        let replacements = (0..report.len()).filter_map(|index| {
+            let neighbours = &report[index.saturating_sub(1)..(index + 2).min(report.len())];
+            let value = report[index];
+            let with = self.best_value_at(report, neighbours, value, |with| Edit::Replace {
+                index,
+                value,
+                with,
+            })?;
+            Some(Edit::Replace { index, value, with })
+        });
+        let insertions = (0..=report.len()).filter_map(|index| {
+            let neighbours = &report[index.saturating_sub(1)..(index + 1).min(report.len())];
+            let midpoint = (neighbours.iter().map(|&n| n as u64).sum::<u64>()
+                / neighbours.len().max(1) as u64) as u32;
+            let value = self.best_value_at(report, neighbours, midpoint, |value| Edit::Insert {
+                index,
+                value,
+            })?;
+            Some(Edit::Insert { index, value })
+        });
+        removals.chain(replacements).chain(insertions).collect()
+    }
+
+    /// The least invasive single edit that makes the report safe, if one exists.
+    pub fn minimal_repair(&self, report: &[u32]) -> Option<Edit> {
+        self.repairs(report)
+            .into_iter()
+            .min_by_key(|edit| match edit {
+                Edit::Remove { .. } => (0, 0),
+                Edit::Replace { value, with, .. } => (1, value.abs_diff(*with)),
+                Edit::Insert { .. } => (2, 0),
+            })
+    }
+
+    pub fn repairability(&self, report: &[u32]) -> Repairability {
+        if self.is_safe(report) {
+            return Repairability::Safe;
+        }
+        match self.minimal_repair(report) {
+            Some(Edit::Remove { .. }) => Repairability::Removal,
+            Some(Edit::Replace { .. }) => Repairability::Replacement,
+            Some(Edit::Insert { .. }) => Repairability::Insertion,
+            None => Repairability::Unrepairable,
+        }
+    }
+
+    /// Whether the report is safe once at most `max_removals` levels are removed.
+    pub fn is_safe_with_removals(&self, report: &[u32], max_removals: usize) -> bool {
+        self.is_safe(report) || self.minimum_removals(report) <= max_removals
+    }
+
+    /// Whether the report is safe once at most one level is removed.
+    pub fn is_safe_with_dampener(&self, report: &[u32]) -> bool {
+        self.is_safe_with_removals(report, 1)
+    }
+}
+
+/// Calls `handler` on each line's report, skipping blank lines. Stops with an error at the first
+/// level that is not a number or does not fit in a `u32`.
+fn iterate_reports<'a, T, H>(
+    input: &'a str,
+    handler: H,
+) -> impl Iterator<Item = Result<T, AocError>> + 'a
 where
-    I: Iterator<Item = T> + 'a,
+    H: Fn(&[u32]) -> T + 'a,
 {
-    iterator
-        .enumerate()
-        .filter_map(|(i, v)| if i == *index { None } else { Some(v) })
+    iterate_numbered_reports(input, handler).map_ok(|(_, result)| result)
+}
+
+/// Like [`iterate_reports`], along with the 1-based line number of each report. Blank lines are
+/// not reports, so they are skipped.
+fn iterate_numbered_reports<'a, T, H>(
+    input: &'a str,
+    handler: H,
+) -> impl Iterator<Item = Result<(usize, T), AocError>> + 'a
+where
+    H: Fn(&[u32]) -> T + 'a,
+{
+    let mut report: Vec<u32> = Vec::with_capacity(20);
+    let mut lexer = Token::lexer(input);
+    let mut line = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        while !done {
+            match lexer.next() {
+                Some(Ok(Token::Number(n))) => report.push(n),
+                Some(Ok(Token::Whitespace)) => {}
+                Some(Ok(Token::Newline)) => {
+                    line += 1;
+                    if !report.is_empty() {
+                        let result = handler(&report);
+                        report.clear();
+                        return Some(Ok((line, result)));
+                    }
+                }
+                Some(Err(error)) => {
+                    done = true;
+                    return Some(Err(error.at(input, lexer.span())));
+                }
+                // The last report need not end with a newline.
+                None => {
+                    done = true;
+                    if !report.is_empty() {
+                        return Some(Ok((line + 1, handler(&report))));
+                    }
+                }
+            }
+        }
+        None
+    })
 }
 
 #[aoc(day2, part1)]
-pub fn part1(input: &str) -> u32 {
+pub fn part1(input: &str) -> Result<u32, AocError> {
+    part1_with_policy(input, &SafetyPolicy::default())
+}
+
+pub fn part1_with_policy(input: &str, policy: &SafetyPolicy) -> Result<u32, AocError> {
+    iterate_reports(input, |report| policy.is_safe(report) as u32).sum()
+}
+
+#[aoc(day2, part2)]
+pub fn part2(input: &str) -> Result<u32, AocError> {
+    part2_with_policy(input, &SafetyPolicy::default())
+}
+
+pub fn part2_with_policy(input: &str, policy: &SafetyPolicy) -> Result<u32, AocError> {
+    iterate_reports(input, |report| policy.is_safe_with_dampener(report) as u32).sum()
+}
+
+/// Counts the reports that are safe once at most `max_removals` levels are removed.
+pub fn count_safe_with_removals(
+    input: &str,
+    policy: &SafetyPolicy,
+    max_removals: usize,
+) -> Result<u32, AocError> {
     iterate_reports(input, |report| {
-        return_first_invalid_index(report.iter()).is_none() as u32
+        policy.is_safe_with_removals(report, max_removals) as u32
     })
     .sum()
 }
 
-#[aoc(day2, part2)]
-pub fn part2(input: &str) -> u32 {
-    iterate_reports(input, |report| {
-        match return_first_invalid_index(report.iter()) {
-            None => 1,
-            Some(first_invalid_index) => {
-                (return_first_invalid_index(skip_index(&first_invalid_index, report.iter()))
-                    .is_none()
-                    || return_first_invalid_index(skip_index(
-                        &(first_invalid_index + 1),
-                        report.iter(),
-                    ))
-                    .is_none()
-                    || return_first_invalid_index(skip_index(&0, report.iter())).is_none())
-                    as u32
+/// Every unsafe report with its 1-based line number and first violation.
+pub fn unsafe_reports<'a>(
+    input: &'a str,
+    policy: &'a SafetyPolicy,
+) -> impl Iterator<Item = Result<(usize, Vec<u32>, Violation), AocError>> + 'a {
+    iterate_numbered_reports(input, |report| {
+        policy
+            .first_violation(report)
+            .map(|violation| (report.to_vec(), violation))
+    })
+    .filter_map(|unsafe_report| {
+        unsafe_report
+            .map(|(line, found)| found.map(|(report, violation)| (line, report, violation)))
+            .transpose()
+    })
+}
+
+/// How repairable each report is.
+pub fn classify_reports(
+    input: &str,
+    policy: &SafetyPolicy,
+) -> Result<Vec<Repairability>, AocError> {
+    iterate_reports(input, |report| policy.repairability(report)).collect()
+}
+
+/// The fewest levels that have to be removed from each report to make it safe.
+pub fn minimum_removals(input: &str, policy: &SafetyPolicy) -> Result<Vec<usize>, AocError> {
+    iterate_reports(input, |report| policy.minimum_removals(report)).collect()
+}
+
+/// Running totals from [`classify_stream`]: reports that are safe as they are, reports that the
+/// problem dampener makes safe, and reports that stay unsafe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamCounts {
+    pub safe: u64,
+    pub dampened: u64,
+    pub unsafe_reports: u64,
+}
+
+impl StreamCounts {
+    fn of_report(policy: &SafetyPolicy, report: &[u32]) -> Self {
+        let mut counts = StreamCounts::default();
+        if policy.is_safe(report) {
+            counts.safe = 1;
+        } else if policy.is_safe_with_dampener(report) {
+            counts.dampened = 1;
+        } else {
+            counts.unsafe_reports = 1;
+        }
+        counts
+    }
+
+    fn then(self, other: StreamCounts) -> Self {
+        StreamCounts {
+            safe: self.safe + other.safe,
+            dampened: self.dampened + other.dampened,
+            unsafe_reports: self.unsafe_reports + other.unsafe_reports,
+        }
+    }
+}
+
+impl fmt::Display for StreamCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "safe: {}, dampened: {}, unsafe: {}",
+            self.safe, self.dampened, self.unsafe_reports
+        )
+    }
+}
+
+/// Whole lines of the input read by [`classify_stream`], starting at line `first_line`.
+struct Block {
+    index: usize,
+    first_line: usize,
+    bytes: Vec<u8>,
+}
+
+impl Block {
+    fn classify(&self, policy: &SafetyPolicy) -> Result<StreamCounts, AocError> {
+        std::str::from_utf8(&self.bytes)
+            .map_err(|error| {
+                let valid = std::str::from_utf8(&self.bytes[..error.valid_up_to()]).unwrap();
+                AocError::lex(Position::of_offset(valid, valid.len()), "invalid UTF-8")
+            })
+            .and_then(|text| {
+                iterate_reports(text, |report| StreamCounts::of_report(policy, report))
+                    .fold_ok(StreamCounts::default(), StreamCounts::then)
+            })
+            .map_err(|error| error.after_lines(self.first_line - 1))
+    }
+}
+
+/// Reads `reader` about `block_size` bytes at a time and hands on everything up to the last
+/// newline so far as a block, so that every report lies in one block. The rest of the input after
+/// the last newline is the final block. Stops early once `send` returns false or `failed` is set.
+fn read_blocks<R: Read>(
+    mut reader: R,
+    block_size: usize,
+    failed: &AtomicBool,
+    mut send: impl FnMut(Block) -> bool,
+) -> Result<(), (usize, AocError)> {
+    let mut buffer = vec![0; block_size.max(1)];
+    let mut pending = vec![];
+    let (mut index, mut first_line) = (0, 1);
+    while !failed.load(Ordering::Relaxed) {
+        let read = match reader.read(&mut buffer) {
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err((index, error.into())),
+        };
+        pending.extend_from_slice(&buffer[..read]);
+        let cut = match (read, memrchr(b'\n', &pending)) {
+            (0, _) => pending.len(),
+            (_, Some(newline)) => newline + 1,
+            (_, None) => continue,
+        };
+        if cut > 0 {
+            let rest = pending.split_off(cut);
+            let bytes = std::mem::replace(&mut pending, rest);
+            let lines = bytes.iter().filter(|&&b| b == b'\n').count();
+            if !send(Block {
+                index,
+                first_line,
+                bytes,
+            }) {
+                break;
+            }
+            index += 1;
+            first_line += lines;
+        }
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Classifies the reports read from `reader` as safe, dampened or unsafe on `workers` threads,
+/// in blocks of about `block_size` bytes cut at newlines. Calls `progress` with the running
+/// totals each time a block is done, in the order the blocks finish. Stops reading at the first
+/// error, and returns the error from the earliest block that failed.
+pub fn classify_stream<R: Read + Send>(
+    reader: R,
+    policy: &SafetyPolicy,
+    block_size: usize,
+    workers: usize,
+    mut progress: impl FnMut(StreamCounts),
+) -> Result<StreamCounts, AocError> {
+    let workers = workers.max(1);
+    // Bounded, so the reader gets at most a couple of blocks ahead of the workers.
+    let (block_sender, block_receiver) = mpsc::sync_channel::<Block>(workers * 2);
+    let block_receiver = Mutex::new(block_receiver);
+    let (result_sender, result_receiver) = mpsc::channel();
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let result_sender = result_sender.clone();
+            let (block_receiver, failed) = (&block_receiver, &failed);
+            s.spawn(move || {
+                loop {
+                    // Let go of the lock before classifying, so the others can take blocks.
+                    let next = block_receiver.lock().unwrap().recv();
+                    let Ok(block) = next else {
+                        break;
+                    };
+                    let result = block.classify(policy);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    if result_sender.send((block.index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        let failed = &failed;
+        s.spawn(move || {
+            let sent = read_blocks(reader, block_size, failed, |block| {
+                block_sender.send(block).is_ok()
+            });
+            if let Err((index, error)) = sent {
+                failed.store(true, Ordering::Relaxed);
+                let _ = result_sender.send((index, Err(error)));
+            }
+        });
+
+        let mut totals = StreamCounts::default();
+        let mut first_error: Option<(usize, AocError)> = None;
+        for (index, result) in result_receiver {
+            match result {
+                Ok(counts) if first_error.is_none() => {
+                    totals = totals.then(counts);
+                    progress(totals);
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    if first_error.as_ref().is_none_or(|(first, _)| index < *first) {
+                        first_error = Some((index, error));
+                    }
+                }
             }
         }
+        match first_error {
+            Some((_, error)) => Err(error),
+            None => Ok(totals),
+        }
     })
-    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Lcg;
+
+    const EXAMPLE: &str = "7 6 4 2 1
+1 2 7 8 9
+9 7 6 2 1
+1 3 2 4 5
+8 6 4 4 1
+1 3 6 7 9
+";
+
+    #[test]
+    fn test_part1() {
+        assert_eq!(part1(EXAMPLE), Ok(2));
+    }
+
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(EXAMPLE), Ok(4));
+    }
+
+    #[test]
+    fn test_classify_stream() {
+        let mut rng = Lcg::new(11);
+        let mut next = |bound| rng.below(bound);
+        let mut input = String::new();
+        for _ in 0..300 {
+            let len = 1 + next(8);
+            let start = 10 + next(10) as u32;
+            let report = (0..len)
+                .scan(start, |level, _| {
+                    *level = (*level + next(9) as u32).saturating_sub(4);
+                    Some(*level)
+                })
+                .join(" ");
+            input.push_str(&report);
+            input.push('\n');
+        }
+        // The last report does not need a trailing newline.
+        input.pop();
+
+        let policy = SafetyPolicy::default();
+        let safe = part1(&input).unwrap() as u64;
+        let with_dampener = part2(&input).unwrap() as u64;
+        let expected = StreamCounts {
+            safe,
+            dampened: with_dampener - safe,
+            unsafe_reports: 300 - with_dampener,
+        };
+        for block_size in [1, 7, 64, 4096] {
+            for workers in [1, 3] {
+                let mut updates = vec![];
+                let counts = classify_stream(input.as_bytes(), &policy, block_size, workers, |c| {
+                    updates.push(c)
+                });
+                assert_eq!(counts, Ok(expected));
+                assert_eq!(updates.last(), Some(&expected));
+                assert!(updates
+                    .iter()
+                    .tuple_windows()
+                    .all(|(a, b)| a.safe <= b.safe && a.unsafe_reports <= b.unsafe_reports));
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify_stream_errors() {
+        let policy = SafetyPolicy::default();
+        let classify = |input: &[u8]| {
+            classify_stream(input, &policy, 4, 3, |_| {})
+                .unwrap_err()
+                .to_string()
+        };
+        // The lines are counted across blocks, and the earliest error wins.
+        assert_eq!(
+            classify(b"1 2 3\n4 5 6\n7 8 9\n1 2 x\n1 y\n"),
+            "invalid input at line 4, column 5: unexpected \"x\""
+        );
+        assert_eq!(
+            classify(b"1 2 3\n4 5 \xff\n"),
+            "invalid input at line 2, column 5: invalid UTF-8"
+        );
+        assert_eq!(
+            classify_stream(&b""[..], &policy, 4, 3, |_| {}),
+            Ok(StreamCounts::default())
+        );
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let policy = SafetyPolicy::default();
+        assert_eq!(part1("1 2 3\n\n\n"), Ok(1));
+        let input = "\n7 6 4 2 1\n\n1 2 7 8 9\n  \n9 7 6 2 1\n1 3 2 4 5\n\n";
+        assert_eq!(part1(input), Ok(1));
+        assert_eq!(part2(input), Ok(2));
+        assert_eq!(count_safe_with_removals(input, &policy, 2), Ok(4));
+        assert_eq!(classify_reports(input, &policy).map(|r| r.len()), Ok(4));
+        assert_eq!(minimum_removals(input, &policy).map(|r| r.len()), Ok(4));
+        assert_eq!(
+            classify_stream(input.as_bytes(), &policy, 4, 3, |_| {}),
+            Ok(StreamCounts {
+                safe: 1,
+                dampened: 1,
+                unsafe_reports: 2
+            })
+        );
+        // Blank lines still count towards the line numbers.
+        let lines = unsafe_reports(input, &policy)
+            .map(|unsafe_report| unsafe_report.unwrap().0)
+            .collect_vec();
+        assert_eq!(lines, vec![4, 6, 7]);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        // The last report does not need a trailing newline.
+        assert_eq!(part1("1 2 3\n3 2 1"), Ok(2));
+        assert_eq!(
+            part1("1 2 3\n4 x 5\n").unwrap_err().to_string(),
+            "invalid input at line 2, column 3: unexpected \"x\""
+        );
+        assert_eq!(
+            part2("1 99999999999 3\n"),
+            Err(AocError::lex(
+                Position::LineColumn { line: 1, column: 3 },
+                "99999999999 is not a valid u32: number too large to fit in target type"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let ascending = SafetyPolicy {
+            direction: Direction::Ascending,
+            ..SafetyPolicy::default()
+        };
+        assert_eq!(part1_with_policy(EXAMPLE, &ascending), Ok(1));
+
+        let wide_steps = SafetyPolicy {
+            max_step: 5,
+            allow_equal: true,
+            ..SafetyPolicy::default()
+        };
+        assert_eq!(part1_with_policy(EXAMPLE, &wide_steps), Ok(5));
+        assert!(wide_steps.is_safe(&[5, 5, 4, 4, 1]));
+        assert!(!wide_steps.is_safe(&[5, 5, 6, 4]));
+        assert!(wide_steps.is_safe_with_dampener(&[5, 5, 6, 4]));
+    }
+
+    #[test]
+    fn test_violations() {
+        let violations = unsafe_reports(EXAMPLE, &SafetyPolicy::default())
+            .map(|unsafe_report| {
+                let (line, _, violation) = unsafe_report.unwrap();
+                (line, violation)
+            })
+            .collect_vec();
+        assert_eq!(
+            violations,
+            vec![
+                (
+                    2,
+                    Violation::StepTooLarge {
+                        index: 1,
+                        from: 2,
+                        to: 7
+                    }
+                ),
+                (
+                    3,
+                    Violation::StepTooLarge {
+                        index: 2,
+                        from: 6,
+                        to: 2
+                    }
+                ),
+                (
+                    4,
+                    Violation::DirectionFlip {
+                        index: 1,
+                        from: 3,
+                        to: 2
+                    }
+                ),
+                (
+                    5,
+                    Violation::ZeroStep {
+                        index: 2,
+                        from: 4,
+                        to: 4
+                    }
+                ),
+            ]
+        );
+
+        let descending_by_two = SafetyPolicy {
+            min_step: 2,
+            direction: Direction::Descending,
+            ..SafetyPolicy::default()
+        };
+        assert_eq!(
+            descending_by_two.first_violation(&[7, 6, 4]),
+            Some(Violation::StepTooSmall {
+                index: 0,
+                from: 7,
+                to: 6
+            })
+        );
+        assert_eq!(
+            descending_by_two.first_violation(&[1, 3]),
+            Some(Violation::DirectionFlip {
+                index: 0,
+                from: 1,
+                to: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_mark_violation() {
+        let violation = Violation::DirectionFlip {
+            index: 1,
+            from: 3,
+            to: 2,
+        };
+        assert_eq!(mark_violation(&[1, 3, 2, 4, 5], &violation), "1 [3 2] 4 5");
+        assert_eq!(violation.to_string(), "direction flip at index 1 (3 -> 2)");
+    }
+
+    #[test]
+    fn test_repairs() {
+        let policy = SafetyPolicy::default();
+        let report = [1, 2, 7, 8, 9];
+        assert_eq!(
+            policy.repairs(&report),
+            vec![
+                Edit::Replace {
+                    index: 1,
+                    value: 2,
+                    with: 4
+                },
+                Edit::Replace {
+                    index: 2,
+                    value: 7,
+                    with: 5
+                },
+                Edit::Insert { index: 2, value: 4 },
+            ]
+        );
+        assert_eq!(
+            policy.minimal_repair(&report).unwrap().to_string(),
+            "replace index 1 (value 2) with 4"
+        );
+        assert_eq!(
+            policy.minimal_repair(&[1, 3, 2, 4, 5]),
+            Some(Edit::Remove { index: 1, value: 3 })
+        );
+        assert_eq!(
+            policy.minimal_repair(&[8, 6, 4, 4, 1]),
+            Some(Edit::Remove { index: 2, value: 4 })
+        );
+        assert_eq!(
+            policy.repairs(&[1, 2, 3, 9, 10, 11]),
+            vec![Edit::Insert { index: 3, value: 6 }]
+        );
+        assert_eq!(policy.minimal_repair(&[1, 5, 9, 13]), None);
+        assert_eq!(policy.repairs(&[7, 6, 4, 2, 1]), vec![]);
+    }
+
+    #[test]
+    fn test_repairs_are_safe() {
+        let policy = SafetyPolicy::default();
+        for report in [
+            [1, 2, 7, 8, 9],
+            [9, 7, 6, 2, 1],
+            [5, 1, 2, 3, 4],
+            [3, 3, 3, 3, 3],
+        ] {
+            for edit in policy.repairs(&report) {
+                assert!(
+                    policy.is_safe(&edit.apply(&report)),
+                    "{:?} {}",
+                    report,
+                    edit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify_reports() {
+        assert_eq!(
+            classify_reports(EXAMPLE, &SafetyPolicy::default()).unwrap(),
+            vec![
+                Repairability::Safe,
+                Repairability::Replacement,
+                Repairability::Replacement,
+                Repairability::Removal,
+                Repairability::Removal,
+                Repairability::Safe,
+            ]
+        );
+        assert_eq!(
+            classify_reports("1 2 3 9 10 11\n1 5 9 13\n", &SafetyPolicy::default()).unwrap(),
+            vec![Repairability::Insertion, Repairability::Unrepairable]
+        );
+    }
+
+    fn is_safe_by_brute_force(policy: &SafetyPolicy, report: &[u32], max_removals: usize) -> bool {
+        (0..=max_removals.min(report.len())).any(|removals| {
+            (0..report.len()).combinations(removals).any(|removed| {
+                let kept = (0..report.len())
+                    .filter(|i| !removed.contains(i))
+                    .map(|i| report[i])
+                    .collect_vec();
+                policy.is_safe(&kept)
+            })
+        })
+    }
+
+    #[test]
+    fn test_minimum_removals() {
+        assert_eq!(
+            minimum_removals(EXAMPLE, &SafetyPolicy::default()),
+            Ok(vec![0, 2, 2, 1, 1, 0])
+        );
+        assert_eq!(
+            count_safe_with_removals(EXAMPLE, &SafetyPolicy::default(), 2),
+            Ok(6)
+        );
+    }
+
+    #[test]
+    fn test_removals_match_brute_force() {
+        let policies = [
+            SafetyPolicy::default(),
+            SafetyPolicy {
+                allow_equal: true,
+                max_step: 4,
+                ..SafetyPolicy::default()
+            },
+            SafetyPolicy {
+                direction: Direction::Descending,
+                min_step: 2,
+                ..SafetyPolicy::default()
+            },
+        ];
+        let mut rng = Lcg::new(37);
+        for _ in 0..400 {
+            let mut next = |bound| rng.below(bound);
+            let len = next(9) as usize;
+            let start = 10 + next(10) as u32;
+            let report = (0..len)
+                .scan(start, |level, _| {
+                    *level = (*level + next(9) as u32).saturating_sub(4);
+                    Some(*level)
+                })
+                .collect_vec();
+            for policy in &policies {
+                for max_removals in 0..=3 {
+                    assert_eq!(
+                        policy.is_safe_with_removals(&report, max_removals),
+                        is_safe_by_brute_force(policy, &report, max_removals),
+                        "{:?} {:?} {}",
+                        report,
+                        policy,
+                        max_removals
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_policy_from_toml() {
+        let policy = SafetyPolicy::from_toml(
+            "max_step = 4\nallow_equal = true\ndirection = \"descending\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            SafetyPolicy {
+                min_step: 1,
+                max_step: 4,
+                allow_equal: true,
+                direction: Direction::Descending,
+            }
+        );
+        assert!(SafetyPolicy::from_toml("max_steps = 4").is_err());
+        assert!(SafetyPolicy::from_toml("direction = \"sideways\"").is_err());
+    }
 }
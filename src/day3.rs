@@ -1,58 +1,1586 @@
+use crate::common::parse_str;
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
+use itertools::Itertools;
 use lazy_static::lazy_static;
+use memchr::{memchr, memchr2, memchr3};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::thread;
 
-lazy_static! {
-    static ref RE: Regex = Regex::new(r"mul\(\d+,\d+\)|don't\(\)|do\(\)").unwrap();
+/// The most arguments any instruction can take.
+pub const MAX_ARITY: usize = 4;
+
+/// What an instruction does to the machine when it runs, given its parsed arguments.
+pub type Effect = fn(&mut Machine, &[i128]);
+
+/// How many digits an operand may have. The puzzle only allows 1-3 digit operands, so `mul(1234,5)`
+/// is corrupted; the lenient rule accepts any digit run that fits in a `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperandRule {
+    #[default]
+    Strict,
+    Lenient,
+}
+
+impl OperandRule {
+    fn max_digits(self) -> Option<usize> {
+        match self {
+            OperandRule::Strict => Some(3),
+            OperandRule::Lenient => None,
+        }
+    }
 }
 
-enum Token {
-    Mul((u32, u32)),
-    Do,
-    Dont,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    Overflow { offset: usize },
+    Io(io::ErrorKind),
 }
 
-fn tokenize(input: &str) -> impl Iterator<Item = Token> + '_ {
-    RE.find_iter(input).map(|m| {
-        match m.as_str().bytes().nth(2).unwrap() {
-            b'l' => {
-                let mut slice = m.as_str();
-                slice = &slice[4..]; // Take off "mul("
-                slice = &slice[..slice.len() - 1]; // Take off ")"
-                let (l, r) = slice.split_at(slice.find(',').unwrap());
-                Token::Mul((l.parse().unwrap(), r[1..].parse().unwrap()))
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::Overflow { offset } => write!(
+                f,
+                "accumulator overflowed by the instruction at byte {}",
+                offset
+            ),
+            MemoryError::Io(kind) => write!(f, "failed to read memory: {}", kind),
+        }
+    }
+}
+
+impl Error for MemoryError {}
+
+impl From<MemoryError> for AocError {
+    fn from(error: MemoryError) -> Self {
+        match error {
+            MemoryError::Overflow { offset } => {
+                AocError::arithmetic(Some(Position::Offset(offset)), "accumulator overflowed")
             }
-            b'(' => Token::Do,
-            b'n' => Token::Dont,
-            _ => unreachable!(),
+            MemoryError::Io(kind) => AocError::Io(kind),
         }
-    })
+    }
 }
 
-#[aoc(day3, part1)]
-pub fn part1(input: &str) -> u32 {
-    tokenize(input)
-        .filter(|t| matches!(t, Token::Mul((_, _))))
-        .map(|t| match t {
-            Token::Mul((l, r)) => l * r,
-            _ => unreachable!(),
+impl From<io::Error> for MemoryError {
+    fn from(error: io::Error) -> Self {
+        MemoryError::Io(error.kind())
+    }
+}
+
+/// The state of the corrupted-memory interpreter: an accumulator and a count of the `don't`
+/// scopes currently open. Instructions only run while no scope is open, unless they are control
+/// instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Machine {
+    pub acc: i64,
+    disabled: u32,
+    overflowed: bool,
+}
+
+impl Machine {
+    /// Sets the accumulator, where `None` means the new value did not fit.
+    pub fn set(&mut self, value: Option<i64>) {
+        match value {
+            Some(value) => self.acc = value,
+            None => self.overflowed = true,
+        }
+    }
+
+    /// Adds to the accumulator, where `None` means the value being added already overflowed.
+    pub fn add(&mut self, value: Option<i128>) {
+        let acc = value
+            .and_then(|value| value.checked_add(self.acc.into()))
+            .and_then(|acc| i64::try_from(acc).ok());
+        self.set(acc);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.disabled == 0
+    }
+
+    /// Closes every open `don't` scope.
+    pub fn enable(&mut self) {
+        self.disabled = 0;
+    }
+
+    /// Disables the machine, forgetting how many scopes were open.
+    pub fn disable(&mut self) {
+        self.disabled = 1;
+    }
+
+    /// Opens a nested `don't` scope.
+    pub fn push_disabled(&mut self) {
+        self.disabled += 1;
+    }
+
+    /// Closes the innermost `don't` scope, if any.
+    pub fn pop_disabled(&mut self) {
+        self.disabled = self.disabled.saturating_sub(1);
+    }
+}
+
+/// The kind of operand an instruction takes: a decimal number, optionally with a leading `-`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgType {
+    #[default]
+    Unsigned,
+    Signed,
+}
+
+impl ArgType {
+    fn pattern(self, operands: OperandRule) -> String {
+        let digits = match operands.max_digits() {
+            Some(max) => format!(r"\d{{1,{}}}", max),
+            None => r"\d+".to_string(),
+        };
+        match self {
+            ArgType::Unsigned => digits,
+            ArgType::Signed => format!("-?{}", digits),
+        }
+    }
+}
+
+/// Parses an operand whose magnitude fits in a `u64`, as matched by `ArgType::pattern`. An
+/// operand that does not parse just means the instruction does not match, like any other
+/// corrupted memory, so the error is dropped.
+fn parse_operand(text: &str) -> Option<i128> {
+    match text.strip_prefix('-') {
+        Some(digits) => parse_str::<u64>(digits).ok().map(|n| -i128::from(n)),
+        None => parse_str::<u64>(text).ok().map(i128::from),
+    }
+}
+
+/// The built-in effects an instruction can have, for instruction sets described by a `Grammar`.
+/// The arithmetic effects work on the product or sum of all the arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectKind {
+    AddProduct,
+    Add,
+    Subtract,
+    Negate,
+    Reset,
+    Enable,
+    Disable,
+    PushDisabled,
+    PopDisabled,
+}
+
+impl EffectKind {
+    pub fn effect(self) -> Effect {
+        match self {
+            EffectKind::AddProduct => |m, args| m.add(EffectKind::AddProduct.addend(args)),
+            EffectKind::Add => |m, args| m.add(EffectKind::Add.addend(args)),
+            EffectKind::Subtract => |m, args| m.add(EffectKind::Subtract.addend(args)),
+            EffectKind::Negate => |m, _| m.set(m.acc.checked_neg()),
+            EffectKind::Reset => |m, _| m.set(Some(0)),
+            EffectKind::Enable => |m, _| m.enable(),
+            EffectKind::Disable => |m, _| m.disable(),
+            EffectKind::PushDisabled => |m, _| m.push_disabled(),
+            EffectKind::PopDisabled => |m, _| m.pop_disabled(),
+        }
+    }
+
+    /// What an arithmetic effect adds to the accumulator, or `None` if it overflows. Effects that
+    /// do not just add something contribute zero.
+    fn addend(self, args: &[i128]) -> Option<i128> {
+        match self {
+            EffectKind::AddProduct => args.iter().try_fold(1i128, |p, &arg| p.checked_mul(arg)),
+            EffectKind::Add => args.iter().try_fold(0i128, |s, &arg| s.checked_add(arg)),
+            EffectKind::Subtract => args.iter().try_fold(0i128, |s, &arg| s.checked_sub(arg)),
+            _ => Some(0),
+        }
+    }
+
+    /// Whether the effect commutes with chunking: it either adds to the accumulator or sets the
+    /// enable state outright, so a chunk's effect only depends on whether it starts enabled.
+    fn is_chunkable(self) -> bool {
+        matches!(
+            self,
+            EffectKind::AddProduct
+                | EffectKind::Add
+                | EffectKind::Subtract
+                | EffectKind::Enable
+                | EffectKind::Disable
+        )
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(
+            self,
+            EffectKind::Enable
+                | EffectKind::Disable
+                | EffectKind::PushDisabled
+                | EffectKind::PopDisabled
+        )
+    }
+}
+
+/// One instruction in a grammar file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstructionSpec {
+    pub name: String,
+    #[serde(default)]
+    pub args: Vec<ArgType>,
+    pub effect: EffectKind,
+}
+
+/// An instruction set described in TOML, so that new puzzle variants only need a new file:
+///
+/// ```toml
+/// operands = "strict"
+///
+/// [[instruction]]
+/// name = "mul"
+/// args = ["unsigned", "unsigned"]
+/// effect = "add_product"
+///
+/// [[instruction]]
+/// name = "don't"
+/// effect = "disable"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Grammar {
+    #[serde(default)]
+    pub operands: OperandRule,
+    #[serde(rename = "instruction", default)]
+    pub instructions: Vec<InstructionSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    Toml(String),
+    NoInstructions,
+    InvalidName(String),
+    DuplicateName(String),
+    TooManyArgs { name: String, count: usize },
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::Toml(message) => write!(f, "invalid grammar file: {}", message),
+            GrammarError::NoInstructions => write!(f, "grammar defines no instructions"),
+            GrammarError::InvalidName(name) => write!(
+                f,
+                "invalid instruction name {:?}; names must be non-empty and cannot contain \
+                 parentheses, commas or digits",
+                name
+            ),
+            GrammarError::DuplicateName(name) => {
+                write!(f, "instruction {:?} is defined more than once", name)
+            }
+            GrammarError::TooManyArgs { name, count } => write!(
+                f,
+                "instruction {:?} takes {} arguments, but at most {} are supported",
+                name, count, MAX_ARITY
+            ),
+        }
+    }
+}
+
+impl Error for GrammarError {}
+
+impl Grammar {
+    pub fn from_toml(source: &str) -> Result<Self, GrammarError> {
+        let grammar: Grammar =
+            toml::from_str(source).map_err(|error| GrammarError::Toml(error.to_string()))?;
+        grammar.validate()?;
+        Ok(grammar)
+    }
+
+    fn validate(&self) -> Result<(), GrammarError> {
+        if self.instructions.is_empty() {
+            return Err(GrammarError::NoInstructions);
+        }
+        for (i, spec) in self.instructions.iter().enumerate() {
+            let name = &spec.name;
+            if name.is_empty() || name.contains(|c: char| "(),".contains(c) || c.is_ascii_digit()) {
+                return Err(GrammarError::InvalidName(name.clone()));
+            }
+            if self.instructions[..i]
+                .iter()
+                .any(|other| &other.name == name)
+            {
+                return Err(GrammarError::DuplicateName(name.clone()));
+            }
+            if spec.args.len() > MAX_ARITY {
+                return Err(GrammarError::TooManyArgs {
+                    name: name.clone(),
+                    count: spec.args.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One entry of an instruction table: the instruction `name(arg,...)` with an argument of each
+/// type in `args`, and its effect. Control instructions run even while the machine is disabled.
+#[derive(Debug, Clone)]
+pub struct InstructionDef {
+    pub name: String,
+    pub args: Vec<ArgType>,
+    pub control: bool,
+    pub effect: Effect,
+    /// The built-in effect, if the instruction has one rather than a custom function.
+    pub kind: Option<EffectKind>,
+}
+
+impl InstructionDef {
+    /// An instruction taking `arity` unsigned arguments.
+    pub fn new(name: &str, arity: usize, effect: Effect) -> Self {
+        InstructionDef::with_args(name, vec![ArgType::Unsigned; arity], effect)
+    }
+
+    pub fn with_args(name: &str, args: Vec<ArgType>, effect: Effect) -> Self {
+        assert!(
+            args.len() <= MAX_ARITY,
+            "{} takes more than {} arguments",
+            name,
+            MAX_ARITY
+        );
+        InstructionDef {
+            name: name.to_string(),
+            args,
+            control: false,
+            effect,
+            kind: None,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+
+    /// An instruction with one of the built-in effects, which is a control instruction if the
+    /// effect changes the enable state.
+    pub fn of_kind(name: &str, args: Vec<ArgType>, kind: EffectKind) -> Self {
+        InstructionDef {
+            control: kind.is_control(),
+            kind: Some(kind),
+            ..InstructionDef::with_args(name, args, kind.effect())
+        }
+    }
+
+    pub fn control(name: &str, arity: usize, effect: Effect) -> Self {
+        InstructionDef {
+            control: true,
+            ..InstructionDef::new(name, arity, effect)
+        }
+    }
+
+    fn pattern(&self, operands: OperandRule) -> String {
+        format!(
+            r"{}\({}\)",
+            regex::escape(&self.name),
+            self.args.iter().map(|arg| arg.pattern(operands)).join(",")
+        )
+    }
+}
+
+/// A scanned instruction: an index into the instruction table, its arguments and the byte range
+/// it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub opcode: usize,
+    pub offset: usize,
+    pub len: usize,
+    args: [i128; MAX_ARITY],
+    arity: usize,
+}
+
+impl Token {
+    pub fn args(&self) -> &[i128] {
+        &self.args[..self.arity]
+    }
+}
+
+/// Why there is no instruction at some offset: either there is none, or the buffer ends before it
+/// can be told whether there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Miss {
+    NoMatch,
+    Incomplete,
+}
+
+fn expect_byte(bytes: &[u8], pos: usize, byte: u8) -> Result<(), Miss> {
+    match bytes.get(pos) {
+        Some(&b) if b == byte => Ok(()),
+        Some(_) => Err(Miss::NoMatch),
+        None => Err(Miss::Incomplete),
+    }
+}
+
+/// The result of scanning for the next instruction: a token and the offset just past it, the
+/// offset of a possible instruction cut off by the end of the buffer, or nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    Found(Token, usize),
+    Pending(usize),
+    Done,
+}
+
+/// A pluggable instruction table. The scanner picks exactly the instructions in the table out of
+/// the corrupted memory and ignores everything else.
+#[derive(Debug, Clone)]
+pub struct InstructionSet {
+    defs: Vec<InstructionDef>,
+    operands: OperandRule,
+    re: Regex,
+    first_bytes: Vec<u8>,
+    is_first_byte: [bool; 256],
+    is_token_byte: [bool; 256],
+}
+
+impl InstructionSet {
+    pub fn new(defs: Vec<InstructionDef>) -> Self {
+        InstructionSet::with_rule(defs, OperandRule::default())
+    }
+
+    fn with_rule(defs: Vec<InstructionDef>, operands: OperandRule) -> Self {
+        // An empty alternation would match everywhere, so an empty table gets a class that
+        // matches nothing instead.
+        let pattern = match defs.is_empty() {
+            true => r"[^\s\S]".to_owned(),
+            false => defs.iter().map(|d| d.pattern(operands)).join("|"),
+        };
+        let re = Regex::new(&pattern).unwrap();
+        let first_bytes = defs
+            .iter()
+            .filter_map(|d| d.name.bytes().next())
+            .unique()
+            .collect_vec();
+        let mut is_first_byte = [false; 256];
+        for &b in &first_bytes {
+            is_first_byte[b as usize] = true;
+        }
+        let mut is_token_byte = [false; 256];
+        for b in defs
+            .iter()
+            .flat_map(|d| d.name.bytes())
+            .chain(*b"0123456789(),-")
+        {
+            is_token_byte[b as usize] = true;
+        }
+        InstructionSet {
+            defs,
+            operands,
+            re,
+            first_bytes,
+            is_first_byte,
+            is_token_byte,
+        }
+    }
+
+    /// Adds an instruction, replacing any existing one with the same name.
+    pub fn with(mut self, def: InstructionDef) -> Self {
+        self.defs.retain(|d| d.name != def.name);
+        self.defs.push(def);
+        InstructionSet::with_rule(self.defs, self.operands)
+    }
+
+    pub fn with_operands(self, operands: OperandRule) -> Self {
+        InstructionSet::with_rule(self.defs, operands)
+    }
+
+    /// Builds the instruction set a grammar describes.
+    pub fn from_grammar(grammar: &Grammar) -> Self {
+        let defs = grammar
+            .instructions
+            .iter()
+            .map(|spec| InstructionDef::of_kind(&spec.name, spec.args.clone(), spec.effect))
+            .collect();
+        InstructionSet::with_rule(defs, grammar.operands)
+    }
+
+    /// Just `mul(a,b)`, which adds `a * b` to the accumulator.
+    pub fn multiply() -> Self {
+        InstructionSet::new(vec![InstructionDef::of_kind(
+            "mul",
+            vec![ArgType::Unsigned; 2],
+            EffectKind::AddProduct,
+        )])
+    }
+
+    /// `mul` with `do()` and `don't()` switching it on and off.
+    pub fn conditional() -> Self {
+        InstructionSet::multiply()
+            .with(InstructionDef::of_kind("do", vec![], EffectKind::Enable))
+            .with(InstructionDef::of_kind(
+                "don't",
+                vec![],
+                EffectKind::Disable,
+            ))
+    }
+
+    /// `mul`, `add(a)`, `sub(a)`, `neg()` and `reset()`, where `do()` and `don't()` nest: each
+    /// `don't()` has to be closed by its own `do()` before instructions run again.
+    pub fn extended() -> Self {
+        let unsigned = vec![ArgType::Unsigned];
+        InstructionSet::multiply()
+            .with(InstructionDef::of_kind(
+                "add",
+                unsigned.clone(),
+                EffectKind::Add,
+            ))
+            .with(InstructionDef::of_kind(
+                "sub",
+                unsigned,
+                EffectKind::Subtract,
+            ))
+            .with(InstructionDef::of_kind("neg", vec![], EffectKind::Negate))
+            .with(InstructionDef::of_kind("reset", vec![], EffectKind::Reset))
+            .with(InstructionDef::of_kind(
+                "do",
+                vec![],
+                EffectKind::PopDisabled,
+            ))
+            .with(InstructionDef::of_kind(
+                "don't",
+                vec![],
+                EffectKind::PushDisabled,
+            ))
+    }
+
+    /// Scans the input in one pass without allocating, jumping between bytes that can start an
+    /// instruction name with memchr and parsing operands as it goes.
+    pub fn tokenize<'a>(&'a self, input: &'a str) -> impl Iterator<Item = Token> + 'a {
+        let bytes = input.as_bytes();
+        let mut pos = 0;
+        std::iter::from_fn(move || match self.scan(bytes, pos, true) {
+            Scan::Found(token, end) => {
+                pos = end;
+                Some(token)
+            }
+            Scan::Pending(_) | Scan::Done => {
+                pos = bytes.len();
+                None
+            }
+        })
+    }
+
+    /// Finds the next instruction at or after `pos`. Unless `complete` is set, more input may
+    /// follow `bytes`, so the scan stops at the first candidate that runs off the end.
+    fn scan(&self, bytes: &[u8], mut pos: usize, complete: bool) -> Scan {
+        while let Some(offset) = self.next_candidate(&bytes[pos..]) {
+            let start = pos + offset;
+            match self.match_at(bytes, start) {
+                Ok((token, end)) => return Scan::Found(token, end),
+                Err(Miss::Incomplete) if !complete => return Scan::Pending(start),
+                Err(_) => pos = start + 1,
+            }
+        }
+        Scan::Done
+    }
+
+    /// The same tokens as `tokenize`, found with a regex built from the table.
+    pub fn tokenize_regex<'a>(&'a self, input: &'a str) -> impl Iterator<Item = Token> + 'a {
+        self.re.find_iter(input).filter_map(|m| {
+            let text = m.as_str();
+            let open = text.find('(').unwrap();
+            let opcode = self
+                .defs
+                .iter()
+                .position(|d| d.name == text[..open])
+                .unwrap();
+            let mut args = [0; MAX_ARITY];
+            let arity = self.defs[opcode].arity();
+            if arity > 0 {
+                for (arg, operand) in args
+                    .iter_mut()
+                    .zip(text[open + 1..text.len() - 1].split(','))
+                {
+                    *arg = parse_operand(operand)?;
+                }
+            }
+            Some(Token {
+                opcode,
+                offset: m.start(),
+                len: m.len(),
+                args,
+                arity,
+            })
+        })
+    }
+
+    fn next_candidate(&self, haystack: &[u8]) -> Option<usize> {
+        match *self.first_bytes.as_slice() {
+            [a] => memchr(a, haystack),
+            [a, b] => memchr2(a, b, haystack),
+            [a, b, c] => memchr3(a, b, c, haystack),
+            _ => haystack
+                .iter()
+                .position(|&b| self.is_first_byte[b as usize]),
+        }
+    }
+
+    /// Matches `name(arg,...)` for some instruction in the table starting at `start`, returning
+    /// the token and the offset just past its closing parenthesis. Operands that are too long for
+    /// the operand rule or do not fit in a `u64` do not match, as in the regex scanner.
+    fn match_at(&self, bytes: &[u8], start: usize) -> Result<(Token, usize), Miss> {
+        let mut miss = Miss::NoMatch;
+        for (opcode, def) in self.defs.iter().enumerate() {
+            match self.match_def(opcode, def, bytes, start) {
+                Ok(found) => return Ok(found),
+                Err(Miss::Incomplete) => miss = Miss::Incomplete,
+                Err(Miss::NoMatch) => {}
+            }
+        }
+        Err(miss)
+    }
+
+    fn match_def(
+        &self,
+        opcode: usize,
+        def: &InstructionDef,
+        bytes: &[u8],
+        start: usize,
+    ) -> Result<(Token, usize), Miss> {
+        let rest = &bytes[start..];
+        if !rest.starts_with(def.name.as_bytes()) {
+            return Err(match def.name.as_bytes().starts_with(rest) {
+                true => Miss::Incomplete,
+                false => Miss::NoMatch,
+            });
+        }
+        let mut pos = start + def.name.len();
+        expect_byte(bytes, pos, b'(')?;
+        pos += 1;
+        let mut args = [0; MAX_ARITY];
+        for (i, (arg, arg_type)) in args.iter_mut().zip(&def.args).enumerate() {
+            if i > 0 {
+                expect_byte(bytes, pos, b',')?;
+                pos += 1;
+            }
+            let operand_start = pos;
+            if *arg_type == ArgType::Signed && bytes.get(pos) == Some(&b'-') {
+                pos += 1;
+            }
+            let digits = bytes[pos..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            if self.operands.max_digits().is_some_and(|max| digits > max) {
+                return Err(Miss::NoMatch);
+            }
+            pos += digits;
+            // The digits may carry on past the end of the buffer.
+            if pos == bytes.len() {
+                return Err(Miss::Incomplete);
+            }
+            // Only ASCII digits and a sign, so always valid UTF-8.
+            *arg = std::str::from_utf8(&bytes[operand_start..pos])
+                .ok()
+                .and_then(parse_operand)
+                .ok_or(Miss::NoMatch)?;
+        }
+        expect_byte(bytes, pos, b')')?;
+        let token = Token {
+            opcode,
+            offset: start,
+            len: pos + 1 - start,
+            args,
+            arity: def.arity(),
+        };
+        Ok((token, pos + 1))
+    }
+
+    pub fn def(&self, opcode: usize) -> &InstructionDef {
+        &self.defs[opcode]
+    }
+
+    pub fn step(&self, machine: &mut Machine, token: &Token) -> Result<(), MemoryError> {
+        let def = &self.defs[token.opcode];
+        if def.control || machine.is_enabled() {
+            (def.effect)(machine, token.args());
+        }
+        if machine.overflowed {
+            return Err(MemoryError::Overflow {
+                offset: token.offset,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, input: &str) -> Result<Machine, MemoryError> {
+        let mut machine = Machine::default();
+        for token in self.tokenize(input) {
+            self.step(&mut machine, &token)?;
+        }
+        Ok(machine)
+    }
+
+    /// Runs memory read from `reader` one buffer at a time, so the whole dump never has to be in
+    /// memory. An instruction cut off by the end of a buffer is kept and finished from the next
+    /// one, and the machine carries its state from buffer to buffer.
+    pub fn run_reader<R: BufRead>(&self, mut reader: R) -> Result<Machine, MemoryError> {
+        let mut machine = Machine::default();
+        let mut pending = vec![];
+        let mut base = 0;
+        loop {
+            let chunk = reader.fill_buf()?;
+            let complete = chunk.is_empty();
+            let read = chunk.len();
+            pending.extend_from_slice(chunk);
+            reader.consume(read);
+            let mut pos = 0;
+            let kept = loop {
+                match self.scan(&pending, pos, complete) {
+                    Scan::Found(mut token, end) => {
+                        token.offset += base;
+                        self.step(&mut machine, &token)?;
+                        pos = end;
+                    }
+                    Scan::Pending(start) => break start,
+                    Scan::Done => break pending.len(),
+                }
+            };
+            pending.drain(..kept);
+            base += kept;
+            if complete {
+                return Ok(machine);
+            }
+        }
+    }
+
+    /// Summarises a chunk of memory for both states it might start in, or `None` if the table has
+    /// an instruction whose effect cannot be summarised that way.
+    pub fn summarize(&self, chunk: &str) -> Option<ChunkSummary> {
+        let kinds: Vec<EffectKind> = self.defs.iter().map(|d| d.kind).collect::<Option<_>>()?;
+        if !kinds.iter().all(|kind| kind.is_chunkable()) {
+            return None;
+        }
+        let mut summary = ChunkSummary::default();
+        for token in self.tokenize(chunk) {
+            let kind = kinds[token.opcode];
+            match kind {
+                EffectKind::Enable => summary.end_state = Some(true),
+                EffectKind::Disable => summary.end_state = Some(false),
+                _ => {
+                    let Some(addend) = kind.addend(token.args()) else {
+                        summary.overflowed = true;
+                        continue;
+                    };
+                    let from_enabled = summary.end_state.unwrap_or(true);
+                    let from_disabled = summary.end_state.unwrap_or(false);
+                    for (enabled, run) in [
+                        (from_enabled, &mut summary.if_enabled),
+                        (from_disabled, &mut summary.if_disabled),
+                    ] {
+                        if enabled {
+                            *run = run.then(RunningSum::of(addend));
+                        }
+                    }
+                }
+            }
+        }
+        Some(summary)
+    }
+
+    /// Splits the input into at most `chunks` pieces, cutting only just before bytes that cannot be
+    /// part of any instruction, so that every instruction lies wholly inside one piece. The cuts
+    /// go before ASCII bytes only, which are always character boundaries.
+    fn split(&self, input: &str, chunks: usize) -> Vec<std::ops::Range<usize>> {
+        let bytes = input.as_bytes();
+        let target = bytes.len().div_ceil(chunks.max(1)).max(1);
+        let mut ranges = vec![];
+        let mut start = 0;
+        while start < bytes.len() {
+            let end = bytes[(start + target).min(bytes.len())..]
+                .iter()
+                .position(|&b| b.is_ascii() && !self.is_token_byte[b as usize])
+                .map_or(bytes.len(), |offset| start + target + offset);
+            ranges.push(start..end);
+            start = end;
+        }
+        ranges
+    }
+
+    /// Runs the input like `run`, but scans and summarises `chunks` pieces of it on separate
+    /// threads and folds the summaries together. Tables with effects that cannot be summarised
+    /// per chunk, and inputs that overflow, fall back to the sequential `run`.
+    pub fn run_parallel(&self, input: &str, chunks: usize) -> Result<i64, MemoryError> {
+        let ranges = self.split(input, chunks);
+        let summaries: Option<Vec<ChunkSummary>> = thread::scope(|s| {
+            let handles = ranges
+                .into_iter()
+                .map(|range| s.spawn(|| self.summarize(&input[range])))
+                .collect_vec();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let total = summaries.map(|summaries| {
+            summaries
+                .into_iter()
+                .fold(ChunkSummary::default(), ChunkSummary::then)
+        });
+        match total {
+            Some(summary) if !summary.overflowed && summary.if_enabled.fits_in_i64() => {
+                Ok(summary.if_enabled.sum as i64)
+            }
+            _ => Ok(self.run(input)?.acc),
+        }
+    }
+
+    /// Runs the input like `run`, recording every instruction and the byte ranges over which the
+    /// machine was enabled.
+    pub fn trace(&self, input: &str) -> Result<Trace, MemoryError> {
+        let mut machine = Machine::default();
+        let mut entries = vec![];
+        let mut spans = vec![];
+        let mut span_start = 0;
+        for token in self.tokenize(input) {
+            let def = self.def(token.opcode);
+            let enabled = machine.is_enabled();
+            self.step(&mut machine, &token)?;
+            entries.push(TraceEntry {
+                offset: token.offset,
+                len: token.len,
+                instruction: def.name.clone(),
+                args: token.args().to_vec(),
+                control: def.control,
+                enabled,
+                acc: machine.acc,
+            });
+            let end = token.offset + token.len;
+            if machine.is_enabled() != enabled && end > span_start {
+                spans.push(Span {
+                    start: span_start,
+                    end,
+                    enabled,
+                });
+                span_start = end;
+            }
+        }
+        if input.len() > span_start {
+            spans.push(Span {
+                start: span_start,
+                end: input.len(),
+                enabled: machine.is_enabled(),
+            });
+        }
+        Ok(Trace {
+            entries,
+            spans,
+            acc: machine.acc,
         })
-        .sum()
+    }
+}
+
+/// The running total of a sequence of additions, with the smallest and largest values it took on
+/// the way, so that overflow of the accumulator at any point can be checked after combining.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunningSum {
+    pub sum: i128,
+    pub min: i128,
+    pub max: i128,
+}
+
+impl RunningSum {
+    fn of(addend: i128) -> Self {
+        RunningSum {
+            sum: addend,
+            min: addend.min(0),
+            max: addend.max(0),
+        }
+    }
+
+    /// The additions of `self` followed by those of `next`. Saturates rather than overflowing,
+    /// which still puts the result out of the accumulator's range.
+    pub fn then(self, next: RunningSum) -> RunningSum {
+        RunningSum {
+            sum: self.sum.saturating_add(next.sum),
+            min: self.min.min(self.sum.saturating_add(next.min)),
+            max: self.max.max(self.sum.saturating_add(next.max)),
+        }
+    }
+
+    fn fits_in_i64(&self) -> bool {
+        self.min >= i64::MIN.into() && self.max <= i64::MAX.into()
+    }
+}
+
+/// What a chunk of memory does to a machine, given whether the machine is enabled when the chunk
+/// starts: the sums it adds either way, and the state it leaves the machine in, where `None`
+/// means it has no `do()` or `don't()` and leaves the state as it was. Summaries form a monoid
+/// under `then`, with the default summary as its identity, so chunks can be summarised in
+/// parallel and folded together in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChunkSummary {
+    pub if_enabled: RunningSum,
+    pub if_disabled: RunningSum,
+    pub end_state: Option<bool>,
+    pub overflowed: bool,
+}
+
+impl ChunkSummary {
+    fn starting(&self, enabled: bool) -> RunningSum {
+        match enabled {
+            true => self.if_enabled,
+            false => self.if_disabled,
+        }
+    }
+
+    pub fn then(self, next: ChunkSummary) -> ChunkSummary {
+        ChunkSummary {
+            if_enabled: self
+                .if_enabled
+                .then(next.starting(self.end_state.unwrap_or(true))),
+            if_disabled: self
+                .if_disabled
+                .then(next.starting(self.end_state.unwrap_or(false))),
+            end_state: next.end_state.or(self.end_state),
+            overflowed: self.overflowed || next.overflowed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Ansi,
+    Html,
+    Json,
+}
+
+/// One scanned instruction. `enabled` is whether the machine was enabled when it was reached, so
+/// an instruction that is neither control nor enabled was skipped; `acc` is the accumulator after
+/// it ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceEntry {
+    pub offset: usize,
+    pub len: usize,
+    pub instruction: String,
+    pub args: Vec<i128>,
+    pub control: bool,
+    pub enabled: bool,
+    pub acc: i64,
+}
+
+/// A byte range of the input over which the machine stayed enabled or disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+    pub spans: Vec<Span>,
+    pub acc: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Highlight {
+    Disabled,
+    Counted,
+    Skipped,
+    Control,
+}
+
+impl Highlight {
+    fn ansi(self) -> &'static str {
+        match self {
+            Highlight::Disabled => "\x1b[2m",
+            Highlight::Counted => "\x1b[1;32m",
+            Highlight::Skipped => "\x1b[9;31m",
+            Highlight::Control => "\x1b[1;33m",
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Highlight::Disabled => "disabled",
+            Highlight::Counted => "counted",
+            Highlight::Skipped => "skipped",
+            Highlight::Control => "control",
+        }
+    }
+}
+
+const HTML_STYLE: &str = "<style>
+.day3-trace .disabled { color: #888; }
+.day3-trace .counted { color: #080; font-weight: bold; }
+.day3-trace .skipped { color: #c00; text-decoration: line-through; }
+.day3-trace .control { color: #a60; font-weight: bold; }
+</style>";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+impl Trace {
+    /// Splits the input into runs of text, each with the highlight it is shown with.
+    fn highlighted<'a>(&self, input: &'a str) -> Vec<(&'a str, Option<Highlight>)> {
+        let mut runs = vec![];
+        let mut entries = self.entries.iter().peekable();
+        for span in &self.spans {
+            let background = (!span.enabled).then_some(Highlight::Disabled);
+            let mut cursor = span.start;
+            while let Some(entry) = entries.next_if(|e| e.offset < span.end) {
+                runs.push((&input[cursor..entry.offset], background));
+                let highlight = match (entry.control, entry.enabled) {
+                    (true, _) => Highlight::Control,
+                    (false, true) => Highlight::Counted,
+                    (false, false) => Highlight::Skipped,
+                };
+                let end = entry.offset + entry.len;
+                runs.push((&input[entry.offset..end], Some(highlight)));
+                cursor = end;
+            }
+            runs.push((&input[cursor..span.end], background));
+        }
+        runs.retain(|(text, _)| !text.is_empty());
+        runs
+    }
+
+    pub fn write_ansi<W: Write>(&self, input: &str, mut out: W) -> io::Result<()> {
+        for (text, highlight) in self.highlighted(input) {
+            match highlight {
+                Some(highlight) => write!(out, "{}{}\x1b[0m", highlight.ansi(), text)?,
+                None => write!(out, "{}", text)?,
+            }
+        }
+        if !input.ends_with('\n') {
+            writeln!(out)?;
+        }
+        writeln!(out, "total: {}", self.acc)
+    }
+
+    pub fn write_html<W: Write>(&self, input: &str, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", HTML_STYLE)?;
+        write!(out, "<pre class=\"day3-trace\">")?;
+        for (text, highlight) in self.highlighted(input) {
+            match highlight {
+                Some(highlight) => write!(
+                    out,
+                    "<span class=\"{}\">{}</span>",
+                    highlight.class(),
+                    escape_html(text)
+                )?,
+                None => write!(out, "{}", escape_html(text))?,
+            }
+        }
+        writeln!(out, "</pre>")?;
+        writeln!(out, "<p>total: {}</p>", self.acc)
+    }
+
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)
+    }
+
+    pub fn write<W: Write>(&self, input: &str, format: TraceFormat, out: W) -> io::Result<()> {
+        match format {
+            TraceFormat::Ansi => self.write_ansi(input, out),
+            TraceFormat::Html => self.write_html(input, out),
+            TraceFormat::Json => self.write_json(out),
+        }
+    }
+}
+
+/// Traces the input with the given instruction set and renders it in the given format.
+pub fn render_trace(
+    set: &InstructionSet,
+    input: &str,
+    format: TraceFormat,
+) -> Result<String, MemoryError> {
+    let mut out = vec![];
+    set.trace(input)?
+        .write(input, format, &mut out)
+        .expect("writing to a Vec cannot fail");
+    Ok(String::from_utf8(out).unwrap())
+}
+
+lazy_static! {
+    static ref MULTIPLY: InstructionSet = InstructionSet::multiply();
+    static ref CONDITIONAL: InstructionSet = InstructionSet::conditional();
+    static ref MULTIPLY_LENIENT: InstructionSet =
+        InstructionSet::multiply().with_operands(OperandRule::Lenient);
+    static ref CONDITIONAL_LENIENT: InstructionSet =
+        InstructionSet::conditional().with_operands(OperandRule::Lenient);
+}
+
+#[aoc(day3, part1)]
+pub fn part1(input: &str) -> Result<i64, AocError> {
+    Ok(MULTIPLY.run(input)?.acc)
+}
+
+#[aoc(day3, part1, Lenient)]
+pub fn part1_lenient(input: &str) -> Result<i64, AocError> {
+    Ok(MULTIPLY_LENIENT.run(input)?.acc)
 }
 
 #[aoc(day3, part2)]
-pub fn part2(input: &str) -> u32 {
-    tokenize(input)
-        .fold((true, 0u32), |(enabled, acc), captures| {
-            if matches!(captures, Token::Do) {
-                (true, acc)
-            } else if !enabled || matches!(captures, Token::Dont) {
-                (false, acc)
-            } else if let Token::Mul((l, r)) = captures {
-                (true, acc + l * r)
-            } else {
-                unreachable!()
+pub fn part2(input: &str) -> Result<i64, AocError> {
+    Ok(CONDITIONAL.run(input)?.acc)
+}
+
+#[aoc(day3, part2, Lenient)]
+pub fn part2_lenient(input: &str) -> Result<i64, AocError> {
+    Ok(CONDITIONAL_LENIENT.run(input)?.acc)
+}
+
+#[aoc(day3, part2, Parallel)]
+pub fn part2_parallel(input: &str) -> Result<i64, AocError> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    Ok(CONDITIONAL.run_parallel(input, threads)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Lcg;
+
+    const EXAMPLE1: &str =
+        "xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+    const EXAMPLE2: &str =
+        "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+
+    #[test]
+    fn test_part1() {
+        assert_eq!(part1(EXAMPLE1), Ok(161));
+    }
+
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(EXAMPLE2), Ok(48));
+    }
+
+    /// Corrupted memory built from fragments of instructions by a fixed-seed LCG.
+    fn random_memory(seed: u64, len: usize) -> String {
+        const FRAGMENTS: [&str; 15] = [
+            "mul(", "mu", "l(", "2", "34", ",", ")", "do()", "don't()", "d", "x", "9999", "é", "→",
+            "💾",
+        ];
+        let mut rng = Lcg::new(seed);
+        (0..len)
+            .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len() as u64) as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_run_reader() {
+        use std::io::BufReader;
+
+        let inputs = [EXAMPLE1.to_string(), EXAMPLE2.to_string()]
+            .into_iter()
+            .chain((0..20).map(|seed| random_memory(seed, 200)));
+        for input in inputs {
+            for set in [
+                InstructionSet::conditional(),
+                InstructionSet::extended().with_operands(OperandRule::Lenient),
+            ] {
+                let expected = set.run(&input).unwrap();
+                for capacity in [1, 2, 3, 5, 8, 13, 64, 4096] {
+                    let reader = BufReader::with_capacity(capacity, input.as_bytes());
+                    assert_eq!(set.run_reader(reader), Ok(expected), "{:?}", input);
+                }
             }
-        })
-        .1
+        }
+        // "mu" + "l(2,4)" and "don" + "'t()" across a two-byte boundary.
+        let reader = BufReader::with_capacity(2, "mul(2,4)don't()mul(5,5)".as_bytes());
+        assert_eq!(CONDITIONAL.run_reader(reader).unwrap().acc, 8);
+        let reader =
+            BufReader::with_capacity(3, "mul(3037000499,3037000499)mul(2,3037000499)".as_bytes());
+        assert_eq!(
+            MULTIPLY_LENIENT.run_reader(reader),
+            Err(MemoryError::Overflow { offset: 26 })
+        );
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        let inputs = [
+            EXAMPLE1.to_string(),
+            EXAMPLE2.to_string(),
+            String::new(),
+            "xéééééé".repeat(10),
+        ]
+        .into_iter()
+        .chain((0..40).map(|seed| random_memory(seed, 300)))
+        .chain((0..10).map(|seed| random_memory(seed, 300).replace("2", "0000000000000000002")))
+        .collect_vec();
+        let sets = [
+            InstructionSet::multiply(),
+            InstructionSet::conditional(),
+            InstructionSet::conditional().with_operands(OperandRule::Lenient),
+            // Has no summary, so runs sequentially.
+            InstructionSet::extended(),
+        ];
+        for set in &sets {
+            for input in &inputs {
+                let expected = set.run(input).map(|m| m.acc);
+                for chunks in [1, 2, 3, 4, 7, 16, 1000] {
+                    assert_eq!(set.run_parallel(input, chunks), expected, "{:?}", input);
+                }
+            }
+        }
+        // The second product alone overflows, so the sum does too, at the same instruction.
+        let lenient = &sets[2];
+        let huge = "mul(3037000499,3037000499) x mul(3037000499,3037000499) x mul(2,1)";
+        assert_eq!(
+            lenient.run_parallel(huge, 3),
+            Err(MemoryError::Overflow { offset: 29 })
+        );
+        // The accumulator goes out of range and comes back, which still overflows.
+        let signed = InstructionSet::from_grammar(
+            &Grammar::from_toml(
+                "operands = \"lenient\"\n[[instruction]]\nname = \"add\"\nargs = [\"signed\"]\n\
+                 effect = \"add\"",
+            )
+            .unwrap(),
+        );
+        let there_and_back = "add(9223372036854775807) add(1) add(-2)";
+        assert_eq!(
+            signed.run_parallel(there_and_back, 3),
+            Err(MemoryError::Overflow { offset: 25 })
+        );
+    }
+
+    #[test]
+    fn test_chunk_summary_monoid() {
+        let set = InstructionSet::conditional();
+        let summaries = (0..30)
+            .map(|seed| set.summarize(&random_memory(seed, 20)).unwrap())
+            .collect_vec();
+        let identity = ChunkSummary::default();
+        for (&a, &b, &c) in summaries.iter().tuple_windows() {
+            assert_eq!(a.then(b).then(c), a.then(b.then(c)));
+            assert_eq!(identity.then(a), a);
+            assert_eq!(a.then(identity), a);
+        }
+        // Summarising a whole input is the same as folding the summaries of its pieces.
+        let input = random_memory(99, 500);
+        let pieces = set.split(&input, 10);
+        assert!(pieces.len() > 1);
+        let folded = pieces
+            .into_iter()
+            .map(|range| set.summarize(&input[range]).unwrap())
+            .fold(identity, ChunkSummary::then);
+        assert_eq!(folded, set.summarize(&input).unwrap());
+        assert_eq!(folded.if_enabled.sum, set.run(&input).unwrap().acc as i128);
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = CONDITIONAL.trace(EXAMPLE2).unwrap();
+        assert_eq!(trace.acc, 48);
+        let summary = trace
+            .entries
+            .iter()
+            .map(|e| (e.offset, e.instruction.as_str(), e.enabled))
+            .collect_vec();
+        assert_eq!(
+            summary,
+            [
+                (1, "mul", true),
+                (20, "don't", true),
+                (28, "mul", false),
+                (48, "mul", false),
+                (59, "do", false),
+                (64, "mul", true),
+            ]
+        );
+        assert_eq!(trace.entries[5].args, [8, 5]);
+        assert_eq!(trace.entries[5].acc, 48);
+        let spans = trace
+            .spans
+            .iter()
+            .map(|s| (s.start, s.end, s.enabled))
+            .collect_vec();
+        assert_eq!(
+            spans,
+            [(0, 27, true), (27, 63, false), (63, EXAMPLE2.len(), true)]
+        );
+    }
+
+    #[test]
+    fn test_render_trace() {
+        let input = "a<mul(2,3)don't()mul(4,5)do()";
+        let ansi = render_trace(&CONDITIONAL, input, TraceFormat::Ansi).unwrap();
+        assert_eq!(
+            ansi,
+            "a<\x1b[1;32mmul(2,3)\x1b[0m\x1b[1;33mdon't()\x1b[0m\x1b[9;31mmul(4,5)\x1b[0m\
+             \x1b[1;33mdo()\x1b[0m\ntotal: 6\n"
+        );
+        let html = render_trace(&CONDITIONAL, input, TraceFormat::Html).unwrap();
+        assert!(html.contains(
+            "<pre class=\"day3-trace\">a&lt;<span class=\"counted\">mul(2,3)</span>\
+             <span class=\"control\">don&#39;t()</span>\
+             <span class=\"skipped\">mul(4,5)</span>\
+             <span class=\"control\">do()</span></pre>"
+        ));
+        let json = render_trace(&CONDITIONAL, input, TraceFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["acc"], 6);
+        assert_eq!(value["entries"][2]["offset"], 17);
+        assert_eq!(value["entries"][2]["enabled"], false);
+        assert_eq!(value["spans"][1]["start"], 17);
+        assert_eq!(value["spans"][1]["end"], 29);
+    }
+
+    #[test]
+    fn test_extended() {
+        let set = InstructionSet::extended();
+        assert_eq!(set.run("add(5)mul(2,3)sub(1)neg()").unwrap().acc, -10);
+        assert_eq!(set.run("mul(2,3)reset()add(7)").unwrap().acc, 7);
+        // The first do() only closes the inner scope, so add(10) is still disabled; the second
+        // closes the outer one and add(100) runs.
+        let machine = set
+            .run("don't()don't()add(1)do()add(10)do()add(100)")
+            .unwrap();
+        assert_eq!(machine.acc, 100);
+        assert!(machine.is_enabled());
+        // Flat do() in the conditional set closes both scopes at once.
+        assert_eq!(
+            CONDITIONAL
+                .run("don't()don't()mul(1,1)do()mul(10,1)")
+                .unwrap()
+                .acc,
+            10
+        );
+    }
+
+    #[test]
+    fn test_scanners_agree() {
+        let inputs = [
+            EXAMPLE1,
+            EXAMPLE2,
+            "mul(1,2mul(3,4)",
+            "mmul(2,2)mul(,3)mul(4,)mul(5,6)don't(do()",
+            "mul(99999999999,2)mul(4294967295,1)mul(4294967296,1)",
+            "mul(99999999999999999999,1)mul(18446744073709551615,1)mul(1234,5)",
+            "add(1)sub(2)neg()reset()neg(1)add()",
+            "",
+            "m",
+            "mul(2,3",
+        ];
+        for set in [
+            InstructionSet::new(vec![]),
+            InstructionSet::multiply(),
+            InstructionSet::conditional(),
+            InstructionSet::extended(),
+            InstructionSet::extended().with_operands(OperandRule::Lenient),
+        ] {
+            let random = (0..20).map(|seed| random_memory(seed, 200)).collect_vec();
+            for input in inputs
+                .iter()
+                .copied()
+                .chain(random.iter().map(String::as_str))
+            {
+                assert_eq!(
+                    set.tokenize(input).collect_vec(),
+                    set.tokenize_regex(input).collect_vec(),
+                    "{:?}",
+                    input
+                );
+            }
+        }
+        // An empty table finds nothing rather than matching everywhere.
+        let empty = InstructionSet::new(vec![]);
+        assert_eq!(empty.tokenize_regex(EXAMPLE2).count(), 0);
+        assert_eq!(empty.run(EXAMPLE2).unwrap().acc, 0);
+    }
+
+    #[test]
+    fn test_custom_instruction() {
+        let set = InstructionSet::conditional()
+            .with(InstructionDef::new("sq", 1, |m, args| {
+                m.add(args[0].checked_pow(2))
+            }))
+            .with(InstructionDef::new("mul", 3, |m, args| {
+                m.add(Some(args.iter().product()))
+            }));
+        assert_eq!(
+            set.run("sq(3)mul(2,3)mul(2,3,4)don't()sq(5)").unwrap().acc,
+            33
+        );
+    }
+
+    const EXTENDED_GRAMMAR: &str = r#"
+[[instruction]]
+name = "mul"
+args = ["unsigned", "unsigned"]
+effect = "add_product"
+
+[[instruction]]
+name = "add"
+args = ["unsigned"]
+effect = "add"
+
+[[instruction]]
+name = "sub"
+args = ["unsigned"]
+effect = "subtract"
+
+[[instruction]]
+name = "neg"
+effect = "negate"
+
+[[instruction]]
+name = "reset"
+effect = "reset"
+
+[[instruction]]
+name = "do"
+effect = "pop_disabled"
+
+[[instruction]]
+name = "don't"
+effect = "push_disabled"
+"#;
+
+    #[test]
+    fn test_grammar() {
+        let from_file =
+            InstructionSet::from_grammar(&Grammar::from_toml(EXTENDED_GRAMMAR).unwrap());
+        let inputs = [
+            "add(5)mul(2,3)sub(1)neg()",
+            "don't()don't()add(1)do()add(10)do()add(100)",
+            EXAMPLE2,
+        ];
+        for input in inputs {
+            assert_eq!(from_file.run(input), InstructionSet::extended().run(input));
+        }
+
+        let variant = Grammar::from_toml(
+            r#"
+operands = "lenient"
+
+[[instruction]]
+name = "mul"
+args = ["signed", "signed", "signed"]
+effect = "add_product"
+
+[[instruction]]
+name = "off"
+effect = "disable"
+
+[[instruction]]
+name = "on"
+effect = "enable"
+"#,
+        )
+        .unwrap();
+        let set = InstructionSet::from_grammar(&variant);
+        assert_eq!(
+            set.run("mul(-2,3,4)mul(2,3)mul(1000,-1,-1)off()mul(1,1,1)on()mul(-,1,1)")
+                .unwrap()
+                .acc,
+            976
+        );
+        assert_eq!(
+            set.tokenize("mul(-2,3,4)mul(2,-,3)mul(-1000,-1,-1)")
+                .collect_vec(),
+            set.tokenize_regex("mul(-2,3,4)mul(2,-,3)mul(-1000,-1,-1)")
+                .collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_grammar_errors() {
+        assert_eq!(
+            Grammar::from_toml("operands = \"strict\""),
+            Err(GrammarError::NoInstructions)
+        );
+        assert!(matches!(
+            Grammar::from_toml("[[instruction]]\nname = \"mul\"\neffect = \"divide\""),
+            Err(GrammarError::Toml(_))
+        ));
+        assert!(matches!(
+            Grammar::from_toml("[[instruction]]\nname = \"mul\"\neffect = \"add\"\nkind = 1"),
+            Err(GrammarError::Toml(_))
+        ));
+        assert_eq!(
+            Grammar::from_toml("[[instruction]]\nname = \"f(x\"\neffect = \"add\""),
+            Err(GrammarError::InvalidName("f(x".to_string()))
+        );
+        assert_eq!(
+            Grammar::from_toml(
+                "[[instruction]]\nname = \"do\"\neffect = \"enable\"\n\
+                 [[instruction]]\nname = \"do\"\neffect = \"disable\""
+            ),
+            Err(GrammarError::DuplicateName("do".to_string()))
+        );
+        assert_eq!(
+            Grammar::from_toml(
+                "[[instruction]]\nname = \"sum\"\nargs = [\"signed\", \"signed\", \
+                 \"signed\", \"signed\", \"signed\"]\neffect = \"add\""
+            ),
+            Err(GrammarError::TooManyArgs {
+                name: "sum".to_string(),
+                count: 5
+            })
+        );
+    }
+
+    #[test]
+    fn test_operand_rules() {
+        let input = "mul(1234,5)mul(12,0003)mul(999,999)mul(0000000000000000000000007,6)";
+        // Only 1-3 digit operands count under the puzzle's rules.
+        assert_eq!(part1(input), Ok(999 * 999));
+        assert_eq!(
+            part1_lenient(input),
+            Ok(1234 * 5 + 12 * 3 + 999 * 999 + 7 * 6)
+        );
+        let disabled = "don't()mul(99999999999999,99999999999999)do()mul(1000,2)mul(100,2)";
+        assert_eq!(part2(disabled), Ok(200));
+        assert_eq!(part2_lenient(disabled), Ok(2200));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // The product of two u64 operands does not fit in the accumulator.
+        let huge = "mul(2,3)mul(18446744073709551615,18446744073709551615)";
+        assert_eq!(
+            part1_lenient(huge),
+            Err(MemoryError::Overflow { offset: 8 }.into())
+        );
+        // Each product fits on its own, but not their sum.
+        let sum = "mul(3037000499,3037000499)xmul(3037000499,3037000499)";
+        assert_eq!(
+            part1_lenient(sum),
+            Err(MemoryError::Overflow { offset: 27 }.into())
+        );
+        assert_eq!(
+            part1_lenient(sum).unwrap_err().to_string(),
+            "arithmetic error at byte 27: accumulator overflowed"
+        );
+        // A disabled instruction cannot overflow.
+        assert_eq!(
+            part2_lenient("don't()mul(18446744073709551615,18446744073709551615)"),
+            Ok(0)
+        );
+        let extended = InstructionSet::extended().with_operands(OperandRule::Lenient);
+        assert_eq!(
+            extended.run("add(9223372036854775807)add(1)"),
+            Err(MemoryError::Overflow { offset: 24 })
+        );
+        assert_eq!(
+            extended
+                .run("sub(9223372036854775807)sub(1)")
+                .map(|m| m.acc),
+            Ok(i64::MIN)
+        );
+        assert_eq!(
+            extended.run("sub(9223372036854775807)sub(1)neg()"),
+            Err(MemoryError::Overflow { offset: 30 })
+        );
+    }
 }
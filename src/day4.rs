@@ -1,7 +1,8 @@
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
-use std::iter::once;
+use std::iter::{once, successors};
 use std::sync::atomic::AtomicU32;
-use std::{cmp, thread};
+use std::thread;
 use std::ops::Shr;
 
 const PART1_PATTERN_BYTES_FORWARD: u32 = u32::from_be_bytes([b'X', b'M', b'A', b'S']);
@@ -9,53 +10,79 @@ const PART1_PATTERN_BYTES_BACKWARD: u32 = u32::from_be_bytes([b'S', b'A', b'M',
 
 const PART2_PATTERN: [[u8; 3]; 3] = [[b'M', 0, b'S'], [0, b'A', 0], [b'M', 0, b'S']];
 
-pub fn input_to_grid<const N: usize>(input: &str) -> [[u8; N]; N] {
-    let mut ret: [[u8; N]; N] = [[0; N]; N];
-    input.lines().enumerate().for_each(|(row, line)| {
-        line.bytes().enumerate().for_each(|(col, c)| {
-            ret[row][col] = c;
-        })
-    });
-    ret
+/// A grid whose size is only known once the input is read. The cells are stored row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
 }
 
-fn iterate_left_to_right<const N: usize>(grid: &[[u8; N]; N]) -> impl Iterator<Item = u8> + '_ {
-    (0..N).flat_map(move |row| {
-        (0..N)
-            .rev()
-            .map(move |col| grid[row][col])
-            .chain(once(b'\n'))
-    })
-}
+impl Grid {
+    /// Reads one row per line. Every row has to be as wide as the first.
+    pub fn parse(input: &str) -> Result<Grid, AocError> {
+        let mut width = None;
+        let mut cells = Vec::with_capacity(input.len());
+        for (row, line) in input.lines().enumerate() {
+            let expected = *width.get_or_insert(line.len());
+            if line.len() != expected {
+                return Err(AocError::structure(
+                    Some(Position::Line(row + 1)),
+                    format!(
+                        "row has {} cells, but the first row has {}",
+                        line.len(),
+                        expected
+                    ),
+                ));
+            }
+            cells.extend_from_slice(line.as_bytes());
+        }
+        match width {
+            Some(width) if width > 0 => Ok(Grid {
+                width,
+                height: cells.len() / width,
+                cells,
+            }),
+            _ => Err(AocError::structure(None, "the grid is empty")),
+        }
+    }
 
-fn iterate_top_to_bottom<const N: usize>(grid: &[[u8; N]; N]) -> impl Iterator<Item = u8> + '_ {
-    (0..N).flat_map(move |col| (0..N).map(move |row| grid[row][col]).chain(once(b'\n')))
-}
+    pub fn width(&self) -> usize {
+        self.width
+    }
 
-fn iterate_bottomright_to_topleft<const N: usize>(
-    grid: &[[u8; N]; N],
-) -> impl Iterator<Item = u8> + '_ {
-    (4..(N * 2 - 3)).flat_map(move |bar| {
-        let start_coord: [usize; 2] = [N.saturating_sub(bar), N.saturating_sub(N * 2 - bar)];
-        let bar_length = cmp::min(bar, N * 2 - bar);
-        (0..bar_length)
-            .rev()
-            .map(move |pos| grid[start_coord[0] + pos][start_coord[1] + pos])
-            .chain(once(b'\n'))
-    })
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The cells row by row, so `(row, col)` is at `row * width + col`.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<u8> {
+        (row < self.height && col < self.width).then(|| self.cells[row * self.width + col])
+    }
+
+    /// The cell `steps` away from `(row, col)` in the direction `step`, if it is on the grid.
+    fn offset(&self, row: usize, col: usize, step: (isize, isize), steps: isize) -> Option<u8> {
+        let row = row.checked_add_signed(step.0 * steps)?;
+        let col = col.checked_add_signed(step.1 * steps)?;
+        self.get(row, col)
+    }
 }
 
-fn iterate_bottomleft_to_topright<const N: usize>(
-    grid: &[[u8; N]; N],
-) -> impl Iterator<Item = u8> + '_ {
-    (4..(N * 2 - 3)).flat_map(move |bar| {
-        let start_coord: [usize; 2] = [N.saturating_sub(N * 2 - bar), cmp::min(bar - 1, N - 1)];
-        let bar_length = cmp::min(bar, N * 2 - bar);
-        (0..bar_length)
-            .rev()
-            .map(move |pos| grid[start_coord[0] + pos][start_coord[1] - pos])
-            .chain(once(b'\n'))
-    })
+/// Every line of cells through the grid in the direction `step`, each followed by a `\n` so that
+/// no match runs from one line into the next.
+fn iterate_lines(grid: &Grid, step: (isize, isize)) -> impl Iterator<Item = u8> + '_ {
+    (0..grid.height())
+        .flat_map(move |row| (0..grid.width()).map(move |col| (row, col)))
+        .filter(move |&(row, col)| grid.offset(row, col, step, -1).is_none())
+        .flat_map(move |(row, col)| {
+            successors(Some(0), |steps| Some(steps + 1))
+                .map_while(move |steps| grid.offset(row, col, step, steps))
+                .chain(once(b'\n'))
+        })
 }
 
 fn count_matches_in_iter<I>(iter: I) -> u32
@@ -77,84 +104,99 @@ where
 }
 
 #[aoc(day4, part1)]
-pub fn part1(input: &str) -> u32 {
-    part1_sized::<140>(input)
-}
-
-fn part1_sized<const N: usize>(input: &str) -> u32 {
-    let grid = input_to_grid::<N>(input);
+pub fn part1(input: &str) -> Result<u32, AocError> {
+    let grid = Grid::parse(input)?;
     let sum = AtomicU32::new(0);
 
     thread::scope(|s| {
-        s.spawn(|| {
-            sum.fetch_add(
-                count_matches_in_iter(iterate_left_to_right(&grid)),
-                std::sync::atomic::Ordering::Relaxed,
-            );
-        });
-        s.spawn(|| {
-            sum.fetch_add(
-                count_matches_in_iter(iterate_bottomright_to_topleft(&grid)),
-                std::sync::atomic::Ordering::Relaxed,
-            );
-        });
-        s.spawn(|| {
-            sum.fetch_add(
-                count_matches_in_iter(iterate_top_to_bottom(&grid)),
-                std::sync::atomic::Ordering::Relaxed,
-            );
-        });
-        s.spawn(|| {
-            sum.fetch_add(
-                count_matches_in_iter(iterate_bottomleft_to_topright(&grid)),
-                std::sync::atomic::Ordering::Relaxed,
-            );
-        });
+        for step in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            let (grid, sum) = (&grid, &sum);
+            s.spawn(move || {
+                sum.fetch_add(
+                    count_matches_in_iter(iterate_lines(grid, step)),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            });
+        }
     });
 
-    sum.into_inner()
+    Ok(sum.into_inner())
 }
 
-trait GridView<const N: usize> {
+trait GridView {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
     fn get(&self, x: usize, y: usize) -> u8;
 }
 
-struct StraightGridView<'a, const N: usize>(&'a [[u8; N]; N]);
-impl<'a, const N: usize> GridView<N> for StraightGridView<'a, N> {
+struct StraightGridView<'a>(&'a Grid);
+impl GridView for StraightGridView<'_> {
+    fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    fn height(&self) -> usize {
+        self.0.height()
+    }
+
     #[inline]
     fn get(&self, x: usize, y: usize) -> u8 {
-        self.0[y][x]
+        self.0.cells[y * self.0.width + x]
     }
 }
 
-struct NinetyDegreeClockwiseGridView<'a, const N: usize>(&'a [[u8; N]; N]);
-impl<'a, const N: usize> GridView<N> for NinetyDegreeClockwiseGridView<'a, N> {
+struct NinetyDegreeClockwiseGridView<'a>(&'a Grid);
+impl GridView for NinetyDegreeClockwiseGridView<'_> {
+    fn width(&self) -> usize {
+        self.0.height()
+    }
+
+    fn height(&self) -> usize {
+        self.0.width()
+    }
+
     #[inline]
     fn get(&self, x: usize, y: usize) -> u8 {
-        self.0[x][N - 1 - y]
+        self.0.cells[x * self.0.width + self.0.width - 1 - y]
     }
 }
 
-struct NinetyDegreeCounterClockwiseGridView<'a, const N: usize>(&'a [[u8; N]; N]);
-impl<'a, const N: usize> GridView<N> for NinetyDegreeCounterClockwiseGridView<'a, N> {
+struct NinetyDegreeCounterClockwiseGridView<'a>(&'a Grid);
+impl GridView for NinetyDegreeCounterClockwiseGridView<'_> {
+    fn width(&self) -> usize {
+        self.0.height()
+    }
+
+    fn height(&self) -> usize {
+        self.0.width()
+    }
+
     #[inline]
     fn get(&self, x: usize, y: usize) -> u8 {
-        self.0[N - 1 - x][y]
+        self.0.cells[(self.0.height - 1 - x) * self.0.width + y]
     }
 }
 
-struct OneEightyDegreeGridView<'a, const N: usize>(&'a [[u8; N]; N]);
-impl<'a, const N: usize> GridView<N> for OneEightyDegreeGridView<'a, N> {
+struct OneEightyDegreeGridView<'a>(&'a Grid);
+impl GridView for OneEightyDegreeGridView<'_> {
+    fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    fn height(&self) -> usize {
+        self.0.height()
+    }
+
     #[inline]
     fn get(&self, x: usize, y: usize) -> u8 {
-        self.0[N - 1 - y][N - 1 - x]
+        self.0.cells[(self.0.height - 1 - y) * self.0.width + self.0.width - 1 - x]
     }
 }
 
-fn count_pattern_in_grid_view<const N: usize, G: GridView<N>>(view: &G) -> u32 {
-    (0..N - 2)
+fn count_pattern_in_grid_view<G: GridView>(view: &G) -> u32 {
+    (0..view.height().saturating_sub(2))
         .flat_map(|sy| {
-            (0..N - 2).map(move |sx| {
+            (0..view.width().saturating_sub(2)).map(move |sx| {
                 (view.get(sx, sy) == PART2_PATTERN[0][0]
                     && view.get(sx + 2, sy) == PART2_PATTERN[0][2]
                     && view.get(sx + 1, sy + 1) == PART2_PATTERN[1][1]
@@ -166,12 +208,8 @@ fn count_pattern_in_grid_view<const N: usize, G: GridView<N>>(view: &G) -> u32 {
 }
 
 #[aoc(day4, part2)]
-pub fn part2(input: &str) -> u32 {
-    part2_sized::<140>(input)
-}
-
-fn part2_sized<const N: usize>(input: &str) -> u32 {
-    let grid = input_to_grid::<N>(input);
+pub fn part2(input: &str) -> Result<u32, AocError> {
+    let grid = Grid::parse(input)?;
     let sum = AtomicU32::new(0);
 
     thread::scope(|s| {
@@ -201,7 +239,7 @@ fn part2_sized<const N: usize>(input: &str) -> u32 {
         });
     });
 
-    sum.into_inner()
+    Ok(sum.into_inner())
 }
 
 #[cfg(test)]
@@ -221,11 +259,47 @@ MXMXAXMASX";
 
     #[test]
     fn test_part1() {
-        assert_eq!(part1_sized::<10>(EXAMPLE), 18);
+        assert_eq!(part1(EXAMPLE), Ok(18));
     }
 
     #[test]
     fn test_part2() {
-        assert_eq!(part2_sized::<10>(EXAMPLE), 9);
+        assert_eq!(part2(EXAMPLE), Ok(9));
+    }
+
+    #[test]
+    fn test_other_sizes() {
+        assert_eq!(part1("XMASAMX"), Ok(2));
+        assert_eq!(part1("X\nM\nA\nS\n"), Ok(1));
+        assert_eq!(part1("X.....\n.M....\n..A...\n...S..\n"), Ok(1));
+        assert_eq!(part1(".....X\n....M.\n...A..\n..S...\n"), Ok(1));
+        assert_eq!(part2("M.S.M\n.A.A.\nM.S.M\n"), Ok(2));
+        assert_eq!(part2("M.S\n.A.\nM.S\n.A.\nM.S\n"), Ok(2));
+        assert_eq!(part2("XM\nAS\n"), Ok(0));
+        let ragged = "MMMSXXMASM\nMSAMXMSMSA\nAMX\n";
+        for result in [part1(ragged), part2(ragged)] {
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "malformed input at line 3: row has 3 cells, but the first row has 10"
+            );
+        }
+    }
+
+    #[test]
+    fn test_grid() {
+        let grid = Grid::parse("ab.\n#cd\n").unwrap();
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(grid.get(1, 0), Some(b'#'));
+        assert_eq!(grid.get(0, 3), None);
+        assert_eq!(grid.get(2, 0), None);
+        assert_eq!(grid.cells(), b"ab.#cd");
+        assert_eq!(
+            Grid::parse("abc\nde\n").unwrap_err().to_string(),
+            "malformed input at line 2: row has 2 cells, but the first row has 3"
+        );
+        assert_eq!(
+            Grid::parse("\n").unwrap_err().to_string(),
+            "malformed input: the grid is empty"
+        );
     }
 }
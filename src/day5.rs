@@ -0,0 +1,335 @@
+use crate::common::{parse_num, LexError};
+use crate::error::{AocError, Position};
+use aoc_runner_derive::aoc;
+use itertools::Itertools;
+use logos::Logos;
+use std::collections::HashSet;
+
+#[derive(Logos, Debug, Clone, Copy, PartialEq, Eq)]
+#[logos(error = LexError)]
+enum Token {
+    #[regex(r"\d+", parse_num)]
+    Page(u32),
+
+    #[token("|")]
+    Bar,
+
+    #[token(",")]
+    Comma,
+
+    #[regex(r"[ \t\r]+", logos::skip)]
+    Whitespace,
+
+    #[token("\n")]
+    Newline,
+}
+
+/// The `X|Y` rules: page `X` has to be printed at some point before page `Y`.
+#[derive(Debug, Default)]
+struct Rules(HashSet<(u32, u32)>);
+
+impl Rules {
+    fn before(&self, first: u32, second: u32) -> bool {
+        self.0.contains(&(first, second))
+    }
+
+    fn is_ordered(&self, pages: &[u32]) -> bool {
+        pages
+            .iter()
+            .tuple_combinations()
+            .all(|(&earlier, &later)| !self.before(later, earlier))
+    }
+
+    /// The pages reordered so that every rule between two of them holds, each page as early as
+    /// the rules allow and in its original order otherwise. Only the rules between the update's
+    /// own pages count, so a cycle through the whole rule set is fine as long as no single update
+    /// closes it; otherwise this returns the pages of the cycle.
+    fn reorder(&self, pages: &[u32]) -> Result<Vec<u32>, Vec<u32>> {
+        let mut in_degree: Vec<usize> = pages
+            .iter()
+            .map(|&page| pages.iter().filter(|&&p| self.before(p, page)).count())
+            .collect();
+        let mut placed = vec![false; pages.len()];
+        let mut ordered = Vec::with_capacity(pages.len());
+        while let Some(next) = (0..pages.len()).find(|&i| !placed[i] && in_degree[i] == 0) {
+            placed[next] = true;
+            ordered.push(pages[next]);
+            for (i, &page) in pages.iter().enumerate() {
+                if self.before(pages[next], page) {
+                    in_degree[i] -= 1;
+                }
+            }
+        }
+        if ordered.len() == pages.len() {
+            return Ok(ordered);
+        }
+
+        // Every page left has a predecessor that is also left, so walking back from any of them
+        // has to come round to a page it has already seen.
+        let mut walk = vec![(0..pages.len()).find(|&i| !placed[i]).unwrap()];
+        loop {
+            let current = pages[*walk.last().unwrap()];
+            let previous = (0..pages.len())
+                .find(|&i| !placed[i] && self.before(pages[i], current))
+                .unwrap();
+            if let Some(start) = walk.iter().position(|&i| i == previous) {
+                let mut cycle: Vec<u32> = walk[start..].iter().rev().map(|&i| pages[i]).collect();
+                cycle.push(cycle[0]);
+                return Err(cycle);
+            }
+            walk.push(previous);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Update {
+    line: usize,
+    pages: Vec<u32>,
+}
+
+impl Update {
+    fn middle(&self) -> u32 {
+        self.pages[self.pages.len() / 2]
+    }
+}
+
+/// Reads the `X|Y` rules and the comma-separated updates, one per line. Blank lines are skipped,
+/// so the usual blank line between the two sections is optional.
+fn parse(input: &str) -> Result<(Rules, Vec<Update>), AocError> {
+    let mut rules = Rules::default();
+    let mut updates = vec![];
+    let mut line = 1;
+    let mut tokens = vec![];
+    let mut lexer = Token::lexer(input);
+    loop {
+        let token = lexer.next();
+        match token {
+            Some(Ok(Token::Newline)) | None => {}
+            Some(Ok(token)) => {
+                tokens.push(token);
+                continue;
+            }
+            Some(Err(error)) => return Err(error.at(input, lexer.span())),
+        }
+
+        match tokens[..] {
+            [] => {}
+            [Token::Page(first), Token::Bar, Token::Page(second)] if first == second => {
+                return Err(AocError::structure(
+                    Some(Position::Line(line)),
+                    format!("page {} cannot be printed before itself", first),
+                ))
+            }
+            [Token::Page(first), Token::Bar, Token::Page(second)] => {
+                rules.0.insert((first, second));
+            }
+            [Token::Page(_), ..] => updates.push(parse_update(&tokens, line)?),
+            _ => {
+                return Err(AocError::structure(
+                    Some(Position::Line(line)),
+                    "expected a rule like 47|53 or an update like 75,47,61",
+                ))
+            }
+        }
+        tokens.clear();
+        if token.is_none() {
+            break;
+        }
+        line += 1;
+    }
+    Ok((rules, updates))
+}
+
+fn parse_update(tokens: &[Token], line: usize) -> Result<Update, AocError> {
+    let error = |message: String| AocError::structure(Some(Position::Line(line)), message);
+    let mut pages = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        match (i % 2, token) {
+            (0, Token::Page(page)) if pages.contains(page) => {
+                return Err(error(format!("page {} appears twice in the update", page)))
+            }
+            (0, Token::Page(page)) => pages.push(*page),
+            (1, Token::Comma) => {}
+            _ => {
+                return Err(error(
+                    "expected an update of comma-separated pages".to_owned(),
+                ))
+            }
+        }
+    }
+    if tokens.len().is_multiple_of(2) {
+        return Err(error("the update ends with a comma".to_owned()));
+    }
+    if pages.len().is_multiple_of(2) {
+        return Err(error(format!(
+            "the update has {} pages, so there is no middle page",
+            pages.len()
+        )));
+    }
+    Ok(Update { line, pages })
+}
+
+fn sum_middles(mut middles: impl Iterator<Item = u32>) -> Result<u32, AocError> {
+    middles
+        .try_fold(0u32, |sum, middle| sum.checked_add(middle))
+        .ok_or_else(|| AocError::arithmetic(None, "sum of the middle pages overflowed"))
+}
+
+#[aoc(day5, part1)]
+pub fn part1(input: &str) -> Result<u32, AocError> {
+    let (rules, updates) = parse(input)?;
+    sum_middles(
+        updates
+            .iter()
+            .filter(|update| rules.is_ordered(&update.pages))
+            .map(Update::middle),
+    )
+}
+
+#[aoc(day5, part2)]
+pub fn part2(input: &str) -> Result<u32, AocError> {
+    let (rules, updates) = parse(input)?;
+    let mut middles = vec![];
+    for update in updates
+        .iter()
+        .filter(|update| !rules.is_ordered(&update.pages))
+    {
+        let pages = rules.reorder(&update.pages).map_err(|cycle| {
+            AocError::structure(
+                Some(Position::Line(update.line)),
+                format!(
+                    "the rules order the update's pages in a cycle: {}",
+                    cycle.iter().join(" before ")
+                ),
+            )
+        })?;
+        middles.push(pages[pages.len() / 2]);
+    }
+    sum_middles(middles.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "47|53
+97|13
+97|61
+97|47
+75|29
+61|13
+75|53
+29|13
+97|29
+53|29
+61|53
+97|53
+61|29
+47|13
+75|47
+97|75
+47|61
+75|61
+47|29
+75|13
+53|13
+
+75,47,61,53,29
+97,61,53,29,13
+75,29,13
+75,97,47,61,53
+61,13,29
+97,13,75,29,47
+";
+
+    #[test]
+    fn test_part1() {
+        assert_eq!(part1(EXAMPLE), Ok(143));
+    }
+
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(EXAMPLE), Ok(123));
+    }
+
+    #[test]
+    fn test_reorder() {
+        let (rules, _) = parse(EXAMPLE).unwrap();
+        assert_eq!(
+            rules.reorder(&[75, 97, 47, 61, 53]),
+            Ok(vec![97, 75, 47, 61, 53])
+        );
+        assert_eq!(
+            rules.reorder(&[97, 13, 75, 29, 47]),
+            Ok(vec![97, 75, 47, 29, 13])
+        );
+        // Each page goes as early as the rules allow, in its original order otherwise.
+        assert_eq!(rules.reorder(&[5, 13, 4, 97, 3]), Ok(vec![5, 4, 97, 13, 3]));
+    }
+
+    #[test]
+    fn test_cycles() {
+        // The rules as a whole form a cycle, but no update holds all three pages.
+        let input = "1|2\n2|3\n3|1\n\n2,1,4\n3,2,5\n";
+        assert_eq!(part2(input), Ok(2 + 3));
+        assert_eq!(
+            part2("1|2\n2|3\n3|1\n\n4,2,3,1,5\n")
+                .unwrap_err()
+                .to_string(),
+            "malformed input at line 5: the rules order the update's pages in a cycle: \
+             3 before 1 before 2 before 3"
+        );
+        assert_eq!(
+            part2("1|2\n2|1\n\n1,2,3\n").unwrap_err().to_string(),
+            "malformed input at line 4: the rules order the update's pages in a cycle: \
+             2 before 1 before 2"
+        );
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        fn error(input: &str) -> String {
+            part1(input).unwrap_err().to_string()
+        }
+        assert_eq!(
+            error("1|2\n1|x\n"),
+            "invalid input at line 2, column 3: unexpected \"x\""
+        );
+        assert_eq!(
+            error("1|99999999999\n"),
+            "invalid input at line 1, column 3: 99999999999 is not a valid u32: \
+             number too large to fit in target type"
+        );
+        assert_eq!(
+            error("1|2|3\n"),
+            "malformed input at line 1: expected an update of comma-separated pages"
+        );
+        assert_eq!(
+            error("1|2\n\n,1\n"),
+            "malformed input at line 3: expected a rule like 47|53 or an update like 75,47,61"
+        );
+        assert_eq!(
+            error("1|2\n1|1\n"),
+            "malformed input at line 2: page 1 cannot be printed before itself"
+        );
+        assert_eq!(
+            part2("1|1\n\n1,2,3\n").unwrap_err().to_string(),
+            "malformed input at line 1: page 1 cannot be printed before itself"
+        );
+        assert_eq!(
+            error("1,2,\n"),
+            "malformed input at line 1: the update ends with a comma"
+        );
+        assert_eq!(
+            error("1,2,1\n"),
+            "malformed input at line 1: page 1 appears twice in the update"
+        );
+        assert_eq!(
+            error("1,2\n"),
+            "malformed input at line 1: the update has 2 pages, so there is no middle page"
+        );
+        // The last line does not need a trailing newline.
+        assert_eq!(part1("1|2\n\n1,2,3"), Ok(2));
+    }
+}
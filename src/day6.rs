@@ -0,0 +1,333 @@
+use crate::day4::Grid;
+use crate::error::{AocError, Position};
+use aoc_runner_derive::aoc;
+use itertools::Either;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+
+    fn from_byte(byte: u8) -> Option<Direction> {
+        match byte {
+            b'^' => Some(Direction::Up),
+            b'>' => Some(Direction::Right),
+            b'v' => Some(Direction::Down),
+            b'<' => Some(Direction::Left),
+            _ => None,
+        }
+    }
+
+    fn turn_right(self) -> Direction {
+        Direction::ALL[(self as usize + 1) % 4]
+    }
+}
+
+/// Marks a jump that walks off the map.
+const EXIT: usize = usize::MAX;
+
+/// A cell the guard reaches for the first time, and the cell and direction the guard walks into it
+/// from.
+#[derive(Debug, Clone, Copy)]
+struct Visit {
+    cell: usize,
+    from: usize,
+    facing: Direction,
+}
+
+/// The lab map, with cells numbered row by row as in [`Grid::cells`].
+struct Lab {
+    width: usize,
+    height: usize,
+    obstacles: Vec<bool>,
+    start: usize,
+    facing: Direction,
+    /// `stops[facing][cell]` is where the guard ends up walking straight from `cell`: the last
+    /// cell before an obstacle, or `EXIT` if the guard leaves the map first.
+    stops: [Vec<usize>; 4],
+}
+
+impl Lab {
+    fn parse(input: &str) -> Result<Lab, AocError> {
+        let grid = Grid::parse(input)?;
+        let mut guard = None;
+        let mut obstacles = Vec::with_capacity(grid.cells().len());
+        for (i, &byte) in grid.cells().iter().enumerate() {
+            let position = Position::LineColumn {
+                line: i / grid.width() + 1,
+                column: i % grid.width() + 1,
+            };
+            obstacles.push(byte == b'#');
+            match (byte, Direction::from_byte(byte)) {
+                (b'.' | b'#', _) => {}
+                (_, Some(_)) if guard.is_some() => {
+                    return Err(AocError::structure(Some(position), "a second guard"))
+                }
+                (_, Some(facing)) => guard = Some((i, facing)),
+                (_, None) => {
+                    return Err(AocError::lex(
+                        position,
+                        format!("unexpected {:?}", char::from(byte)),
+                    ))
+                }
+            }
+        }
+        let Some((start, facing)) = guard else {
+            return Err(AocError::structure(None, "there is no guard on the map"));
+        };
+
+        let mut lab = Lab {
+            width: grid.width(),
+            height: grid.height(),
+            obstacles,
+            start,
+            facing,
+            stops: Default::default(),
+        };
+        for facing in Direction::ALL {
+            lab.stops[facing as usize] = lab.jump_table(facing);
+        }
+        Ok(lab)
+    }
+
+    /// The next cell in `facing`, unless that is off the map.
+    fn step(&self, cell: usize, facing: Direction) -> Option<usize> {
+        let (row, col) = (cell / self.width, cell % self.width);
+        match facing {
+            Direction::Up => (row > 0).then(|| cell - self.width),
+            Direction::Right => (col + 1 < self.width).then(|| cell + 1),
+            Direction::Down => (row + 1 < self.height).then(|| cell + self.width),
+            Direction::Left => (col > 0).then(|| cell - 1),
+        }
+    }
+
+    fn jump_table(&self, facing: Direction) -> Vec<usize> {
+        let mut stops = vec![EXIT; self.obstacles.len()];
+        // Fill in the cells nearest the edge the guard walks towards first, so the next cell's
+        // stop is always known.
+        let cells = match facing {
+            Direction::Up | Direction::Left => Either::Left(0..stops.len()),
+            Direction::Right | Direction::Down => Either::Right((0..stops.len()).rev()),
+        };
+        for cell in cells {
+            stops[cell] = match self.step(cell, facing) {
+                None => EXIT,
+                Some(next) if self.obstacles[next] => cell,
+                Some(next) => stops[next],
+            };
+        }
+        stops
+    }
+
+    /// How many steps in `facing` it takes to get from `cell` to `target`, if `target` is ahead
+    /// of it in a straight line.
+    fn distance(&self, cell: usize, facing: Direction, target: usize) -> Option<usize> {
+        let (row, col) = (cell / self.width, cell % self.width);
+        let (target_row, target_col) = (target / self.width, target % self.width);
+        match facing {
+            Direction::Up if col == target_col => row.checked_sub(target_row),
+            Direction::Down if col == target_col => target_row.checked_sub(row),
+            Direction::Left if row == target_row => col.checked_sub(target_col),
+            Direction::Right if row == target_row => target_col.checked_sub(col),
+            _ => None,
+        }
+    }
+
+    /// Walks the guard's patrol one cell at a time, listing each cell the first time the guard
+    /// reaches it, starting with the guard's own.
+    fn patrol(&self) -> Result<Vec<Visit>, AocError> {
+        let mut visits = vec![Visit {
+            cell: self.start,
+            from: self.start,
+            facing: self.facing,
+        }];
+        // One bit per direction the guard has left each cell in.
+        let mut seen = vec![0u8; self.obstacles.len()];
+        let (mut cell, mut facing) = (self.start, self.facing);
+        while let Some(next) = self.step(cell, facing) {
+            if seen[cell] & (1 << facing as u8) != 0 {
+                return Err(AocError::structure(
+                    None,
+                    "the guard walks in a loop and never leaves the map",
+                ));
+            }
+            seen[cell] |= 1 << facing as u8;
+            if self.obstacles[next] {
+                facing = facing.turn_right();
+                continue;
+            }
+            if seen[next] == 0 && next != self.start {
+                visits.push(Visit {
+                    cell: next,
+                    from: cell,
+                    facing,
+                });
+            }
+            cell = next;
+        }
+        Ok(visits)
+    }
+
+    /// Whether the guard walks in a loop, starting from `cell` facing `facing`, once there is an
+    /// extra obstacle at `obstacle`. Jumps from stop to stop with the jump tables, cutting a
+    /// jump short when the extra obstacle is in the way, and only remembers the turns.
+    fn loops_with(
+        &self,
+        obstacle: usize,
+        mut cell: usize,
+        mut facing: Direction,
+        turns: &mut HashSet<(usize, Direction)>,
+    ) -> bool {
+        turns.clear();
+        loop {
+            let stop = self.stops[facing as usize][cell];
+            let blocked = self
+                .distance(cell, facing, obstacle)
+                .filter(|&steps| {
+                    steps > 0
+                        && (stop == EXIT
+                            || self
+                                .distance(cell, facing, stop)
+                                .is_some_and(|s| steps <= s))
+                })
+                .is_some();
+            cell = match (blocked, stop) {
+                (true, _) => self
+                    .step(obstacle, facing.turn_right().turn_right())
+                    .unwrap(),
+                (false, EXIT) => return false,
+                (false, stop) => stop,
+            };
+            if !turns.insert((cell, facing)) {
+                return true;
+            }
+            facing = facing.turn_right();
+        }
+    }
+}
+
+#[aoc(day6, part1)]
+pub fn part1(input: &str) -> Result<usize, AocError> {
+    Ok(Lab::parse(input)?.patrol()?.len())
+}
+
+/// Tries an obstacle on each cell of the patrol except the guard's own. The patrol is the same
+/// up to the first time the guard reaches that cell, so each check starts from there.
+#[aoc(day6, part2)]
+pub fn part2(input: &str) -> Result<usize, AocError> {
+    let lab = Lab::parse(input)?;
+    let mut turns = HashSet::new();
+    Ok(lab.patrol()?[1..]
+        .iter()
+        .filter(|visit| lab.loops_with(visit.cell, visit.from, visit.facing, &mut turns))
+        .count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Lcg;
+
+    const EXAMPLE: &str = "....#.....
+.........#
+..........
+..#.......
+.......#..
+..........
+.#..^.....
+........#.
+#.........
+......#...
+";
+
+    #[test]
+    fn test_part1() {
+        assert_eq!(part1(EXAMPLE), Ok(41));
+    }
+
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(EXAMPLE), Ok(6));
+    }
+
+    /// Whether the guard loops, walking one cell at a time with the obstacle in the map.
+    fn loops_by_walking(lab: &mut Lab, obstacle: usize) -> bool {
+        lab.obstacles[obstacle] = true;
+        let (mut cell, mut facing) = (lab.start, lab.facing);
+        let mut seen = HashSet::new();
+        let loops = loop {
+            if !seen.insert((cell, facing)) {
+                break true;
+            }
+            match lab.step(cell, facing) {
+                None => break false,
+                Some(next) if lab.obstacles[next] => facing = facing.turn_right(),
+                Some(next) => cell = next,
+            }
+        };
+        lab.obstacles[obstacle] = false;
+        loops
+    }
+
+    #[test]
+    fn test_jumps_agree_with_walking() {
+        let mut rng = Lcg::new(0x2545f491);
+        for _ in 0..50 {
+            let (width, height) = (3 + rng.below(15) as usize, 3 + rng.below(15) as usize);
+            let mut map: Vec<u8> = (0..width * height)
+                .map(|_| if rng.below(6) == 0 { b'#' } else { b'.' })
+                .collect();
+            let start = rng.below(map.len() as u64) as usize;
+            map[start] = b"^>v<"[rng.below(4) as usize];
+            let input: String = map
+                .chunks(width)
+                .map(|row| format!("{}\n", std::str::from_utf8(row).unwrap()))
+                .collect();
+
+            let mut lab = Lab::parse(&input).unwrap();
+            let Ok(visits) = lab.patrol() else {
+                continue;
+            };
+            let expected = visits[1..]
+                .iter()
+                .filter(|visit| loops_by_walking(&mut lab, visit.cell))
+                .count();
+            assert_eq!(part2(&input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        fn error(input: &str) -> String {
+            part1(input).unwrap_err().to_string()
+        }
+        assert_eq!(
+            error("..#\n.x^\n"),
+            "invalid input at line 2, column 2: unexpected 'x'"
+        );
+        assert_eq!(
+            error(".^.\n..<\n"),
+            "malformed input at line 2, column 3: a second guard"
+        );
+        assert_eq!(
+            error("...\n...\n"),
+            "malformed input: there is no guard on the map"
+        );
+        assert_eq!(
+            error(".#..\n.^.#\n#...\n..#.\n"),
+            "malformed input: the guard walks in a loop and never leaves the map"
+        );
+    }
+}
@@ -1,15 +1,16 @@
-use crate::common::parse_long;
+use crate::common::{parse_num, parse_str, LexError};
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
-use itertools::Itertools;
-use logos::{Lexer, Logos};
 use bitvec::prelude::*;
+use logos::{Lexer, Logos};
 
 #[derive(Logos, Debug, PartialEq, Eq)]
+#[logos(error = LexError)]
 enum Token {
     #[regex(r"\d+:", parse_answer, priority = 100)]
     Test(u64),
 
-    #[regex(r"\d+", parse_long)]
+    #[regex(r"\d+", parse_num)]
     Term(u64),
 
     #[token(" ", logos::skip)]
@@ -19,10 +20,8 @@ enum Token {
     Newline,
 }
 
-fn parse_answer(lex: &mut Lexer<Token>) -> Option<u64> {
-    Into::<&str>::into(lex.slice().strip_suffix(':').unwrap())
-        .parse()
-        .ok()
+fn parse_answer(lex: &mut Lexer<Token>) -> Result<u64, LexError> {
+    parse_str(lex.slice().strip_suffix(':').unwrap())
 }
 
 #[derive(Debug)]
@@ -37,46 +36,81 @@ enum EquationAttemptOutcome {
     TooBig(usize),
 }
 
-impl Equation {
-    fn from_tokens<I>(tokens: &mut I) -> Option<Self>
-    where
-        I: Iterator<Item = Token>,
-    {
-        if let Some(Token::Test(answer)) = tokens.next() {
-            let terms = tokens
-                .take_while(|t| matches!(t, Token::Term(_)))
-                .map(|t| {
-                    if let Token::Term(n) = t {
-                        n
-                    } else {
-                        unreachable!()
-                    }
-                })
-                .collect();
-            Some(Equation { answer, terms })
-        } else {
-            None
-        }
-    }
+/// The most terms an equation can have, one bit of the operator sequence per gap between terms.
+const MAX_TERMS: usize = 16;
 
-    fn minimum_answer(&self) -> u64 {
-        self.terms.iter().sum()
+/// Reads one `answer: term term ...` equation per line, skipping blank lines.
+fn parse_equations(input: &str) -> Result<Vec<Equation>, AocError> {
+    let mut equations = vec![];
+    let mut current: Option<Equation> = None;
+    let mut line = 1;
+    let mut lexer = Token::lexer(input);
+    while let Some(token) = lexer.next() {
+        match token {
+            Ok(Token::Test(answer)) => {
+                if current.is_some() {
+                    return Err(AocError::structure(
+                        Some(Position::Line(line)),
+                        "more than one answer in the equation",
+                    ));
+                }
+                current = Some(Equation {
+                    answer,
+                    terms: vec![],
+                });
+            }
+            Ok(Token::Term(term)) => match current.as_mut() {
+                Some(equation) => equation.terms.push(term),
+                None => {
+                    return Err(AocError::structure(
+                        Some(Position::Line(line)),
+                        "term before the answer",
+                    ))
+                }
+            },
+            Ok(Token::Newline) => {
+                equations.extend(current.take().map(|e| e.checked(line)).transpose()?);
+                line += 1;
+            }
+            Ok(Token::Whitespace) => {}
+            Err(error) => return Err(error.at(input, lexer.span())),
+        }
     }
+    equations.extend(current.map(|e| e.checked(line)).transpose()?);
+    Ok(equations)
+}
 
-    fn maximum_answer(&self) -> u64 {
-        self.terms.iter().product()
+impl Equation {
+    /// The equation, if it has between one and `MAX_TERMS` terms.
+    fn checked(self, line: usize) -> Result<Self, AocError> {
+        match self.terms.len() {
+            0 => Err(AocError::structure(
+                Some(Position::Line(line)),
+                "equation has no terms",
+            )),
+            n if n > MAX_TERMS => Err(AocError::structure(
+                Some(Position::Line(line)),
+                format!(
+                    "equation has {} terms, but at most {} are supported",
+                    n, MAX_TERMS
+                ),
+            )),
+            _ => Ok(self),
+        }
     }
 
     fn attempt<const L: usize>(&self, seq: &BitSlice<u32>) -> EquationAttemptOutcome {
         let mut acc = self.terms[0];
         for i in 1..L {
-            acc = if seq[i - 1] {
-                acc * self.terms[i]
+            let next = if seq[L - 1 - i] {
+                acc.checked_mul(self.terms[i])
             } else {
-                acc + self.terms[i]
+                acc.checked_add(self.terms[i])
             };
-            if acc > self.answer {
-                return EquationAttemptOutcome::TooBig(i);
+            // Anything that overflows a u64 is too big for the answer too.
+            match next {
+                Some(next) if next <= self.answer => acc = next,
+                _ => return EquationAttemptOutcome::TooBig(i),
             }
         }
         if acc == self.answer {
@@ -94,19 +128,21 @@ impl Equation {
             if let EquationAttemptOutcome::Found = outcome {
                 return true;
             }
+            // The first operator is the highest bit, so every sequence sharing a prefix that is
+            // already too big sits in one aligned block below `i`.
+            if let EquationAttemptOutcome::TooBig(index) = outcome {
+                i[0] &= !(2u32.pow((L - 1 - index) as u32) - 1);
+            }
             if i[0] == 0 {
                 return false;
             }
-            if let EquationAttemptOutcome::TooBig(index) = outcome {
-                i[0] -= 2u32.pow((L - index - 1) as u32);
-            }
             i[0] -= 1;
         }
     }
 
     fn has_possible_solution_part_1(&self) -> bool {
         match self.terms.len() {
-            0 => unreachable!(),
+            0 => false,
             1 => self.terms[0] == self.answer,
             2 => self.has_possible_solution_part_1_sized::<2>(),
             3 => self.has_possible_solution_part_1_sized::<3>(),
@@ -123,19 +159,18 @@ impl Equation {
             14 => self.has_possible_solution_part_1_sized::<14>(),
             15 => self.has_possible_solution_part_1_sized::<15>(),
             16 => self.has_possible_solution_part_1_sized::<16>(),
-            _ => unreachable!()
+            _ => unreachable!("equations have at most {} terms", MAX_TERMS),
         }
     }
 }
 
 #[aoc(day7, part1)]
-pub fn part1(input: &str) -> u64 {
-    Token::lexer(input)
-        .filter_map(|r| r.ok())
-        .batching(Equation::from_tokens)
+pub fn part1(input: &str) -> Result<u64, AocError> {
+    parse_equations(input)?
+        .into_iter()
         .filter(|equation| equation.has_possible_solution_part_1())
-        .map(|equation| equation.answer)
-        .sum()
+        .try_fold(0u64, |sum, equation| sum.checked_add(equation.answer))
+        .ok_or_else(|| AocError::arithmetic(None, "sum of the answers overflowed"))
 }
 
 #[cfg(test)]
@@ -161,6 +196,62 @@ mod tests {
     fn test_equation_from_tokens() {
         let input = "100: 10 10 1 1 1 1 1 1 1 1
 ";
-        assert_eq!(part1(input), 100);
+        assert_eq!(part1(input), Ok(100));
+    }
+
+    #[test]
+    fn test_part1() {
+        let input = "190: 10 19
+3267: 81 40 27
+83: 17 5
+156: 15 6
+7290: 6 8 6 15
+161011: 16 10 13
+192: 17 8 14
+21037: 9 7 18 13
+292: 11 6 16 20";
+        assert_eq!(part1(input), Ok(3749));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Products that overflow a u64 are just too big.
+        assert_eq!(part1("5: 4294967296 4294967296 5\n"), Ok(0));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        fn error(input: &str) -> String {
+            part1(input).unwrap_err().to_string()
+        }
+        assert_eq!(
+            error("1: 1\n2: 3 x\n"),
+            "invalid input at line 2, column 6: unexpected \"x\""
+        );
+        assert_eq!(
+            error("1: 99999999999999999999\n"),
+            "invalid input at line 1, column 4: 99999999999999999999 is not a valid u64: \
+             number too large to fit in target type"
+        );
+        assert_eq!(
+            error("\n3:\n"),
+            "malformed input at line 2: equation has no terms"
+        );
+        assert_eq!(
+            error("3 4\n"),
+            "malformed input at line 1: term before the answer"
+        );
+        assert_eq!(
+            error("3: 1 4: 2\n"),
+            "malformed input at line 1: more than one answer in the equation"
+        );
+        assert_eq!(
+            error(&format!("1: {}\n", "1 ".repeat(17))),
+            "malformed input at line 1: equation has 17 terms, but at most 16 are supported"
+        );
+        assert_eq!(
+            error("18446744073709551615: 18446744073709551615\n1: 1\n"),
+            "arithmetic error: sum of the answers overflowed"
+        );
     }
 }
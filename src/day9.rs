@@ -1,9 +1,199 @@
+use crate::error::{AocError, Position};
 use aoc_runner_derive::aoc;
 use itertools::Itertools;
-use std::collections::VecDeque;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::iter::once;
+use std::ops::Range;
 
 const EMPTY: usize = usize::MAX;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskMapError {
+    InvalidDigit { position: usize, byte: u8 },
+    ChecksumOverflow { file_id: usize },
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for DiskMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskMapError::InvalidDigit { position, byte } => write!(
+                f,
+                "invalid disk map digit '{}' at byte {}",
+                char::from(*byte).escape_default(),
+                position
+            ),
+            DiskMapError::ChecksumOverflow { file_id } => write!(
+                f,
+                "checksum overflowed while adding file {}; use the wide (u128) checksum",
+                file_id
+            ),
+            DiskMapError::Io(kind) => write!(f, "failed to read disk map: {}", kind),
+        }
+    }
+}
+
+impl Error for DiskMapError {}
+
+impl From<DiskMapError> for AocError {
+    fn from(error: DiskMapError) -> Self {
+        match error {
+            DiskMapError::InvalidDigit { position, byte } => AocError::lex(
+                Position::Offset(position),
+                format!(
+                    "invalid disk map digit '{}'",
+                    char::from(byte).escape_default()
+                ),
+            ),
+            DiskMapError::ChecksumOverflow { .. } => AocError::arithmetic(None, error.to_string()),
+            DiskMapError::Io(kind) => AocError::Io(kind),
+        }
+    }
+}
+
+impl From<io::Error> for DiskMapError {
+    fn from(error: io::Error) -> Self {
+        DiskMapError::Io(error.kind())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Walks the bytes of `remaining` in a shared seekable reader in one direction, yielding each
+/// byte with its offset and refilling a fixed-size buffer whenever it runs dry. Several cursors
+/// can share one reader because each seeks to its own offset before every refill.
+struct ChunkedBytes<'a, R> {
+    reader: &'a RefCell<R>,
+    remaining: Range<u64>,
+    direction: Direction,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    cursor: usize,
+}
+
+impl<'a, R: Read + Seek> ChunkedBytes<'a, R> {
+    fn new(
+        reader: &'a RefCell<R>,
+        range: Range<u64>,
+        direction: Direction,
+        chunk_size: usize,
+    ) -> Self {
+        ChunkedBytes {
+            reader,
+            remaining: range,
+            direction,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            cursor: 0,
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let length = (self.remaining.end - self.remaining.start).min(self.chunk_size as u64);
+        let offset = match self.direction {
+            Direction::Forward => self.remaining.start,
+            Direction::Backward => self.remaining.end - length,
+        };
+        self.buffer.resize(length as usize, 0);
+        let mut reader = self.reader.borrow_mut();
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut self.buffer)?;
+        match self.direction {
+            Direction::Forward => {
+                self.remaining.start += length;
+                self.cursor = 0;
+            }
+            Direction::Backward => {
+                self.remaining.end -= length;
+                self.cursor = self.buffer.len();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Iterator for ChunkedBytes<'_, R> {
+    type Item = Result<(u64, u8), DiskMapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let exhausted = match self.direction {
+            Direction::Forward => self.cursor == self.buffer.len(),
+            Direction::Backward => self.cursor == 0,
+        };
+        if exhausted {
+            if self.remaining.is_empty() {
+                return None;
+            }
+            if let Err(error) = self.refill() {
+                self.remaining = 0..0;
+                self.buffer.clear();
+                self.cursor = 0;
+                return Some(Err(error.into()));
+            }
+        }
+        Some(Ok(match self.direction {
+            Direction::Forward => {
+                let position = self.remaining.start - (self.buffer.len() - self.cursor) as u64;
+                self.cursor += 1;
+                (position, self.buffer[self.cursor - 1])
+            }
+            Direction::Backward => {
+                self.cursor -= 1;
+                (
+                    self.remaining.end + self.cursor as u64,
+                    self.buffer[self.cursor],
+                )
+            }
+        }))
+    }
+}
+
+/// Finds the byte range of a disk map in a reader once surrounding whitespace is skipped.
+fn trimmed_range<R: Read + Seek>(
+    reader: &RefCell<R>,
+    chunk_size: usize,
+) -> Result<Range<u64>, DiskMapError> {
+    let len = reader.borrow_mut().seek(SeekFrom::End(0))?;
+    let first_non_whitespace = |range, direction| {
+        ChunkedBytes::new(reader, range, direction, chunk_size)
+            .find(|byte| !matches!(byte, Ok((_, b)) if b.is_ascii_whitespace()))
+            .transpose()
+    };
+    let Some((start, _)) = first_non_whitespace(0..len, Direction::Forward)? else {
+        return Ok(0..0);
+    };
+    let (last, _) = first_non_whitespace(start..len, Direction::Backward)?.unwrap();
+    Ok(start..last + 1)
+}
+
+/// Trims surrounding whitespace from a disk map and checks that every remaining byte is a digit.
+/// A map of even length ends with a trailing gap, which never affects the checksum, so it is
+/// dropped here to keep both cursors aligned on a file at either end.
+fn parse_disk_map(input: &str) -> Result<&[u8], DiskMapError> {
+    let leading_whitespace = input.len() - input.trim_start().len();
+    let digits = input.trim().as_bytes();
+    if let Some(position) = digits.iter().position(|b| !b.is_ascii_digit()) {
+        return Err(DiskMapError::InvalidDigit {
+            position: leading_whitespace + position,
+            byte: digits[position],
+        });
+    }
+    if digits.len().is_multiple_of(2) {
+        Ok(&digits[..digits.len().saturating_sub(1)])
+    } else {
+        Ok(digits)
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct FileSystemEntry {
@@ -11,67 +201,185 @@ pub struct FileSystemEntry {
     length: usize,
 }
 
-fn parse_entries_and_gaps_forward<'a>(
-    input: &'a str,
-) -> impl Iterator<Item = [FileSystemEntry; 2]> + 'a {
-    input
-        .bytes()
-        .into_iter()
-        .map(|b| (b - ('0' as u8)) as usize)
+fn parse_entries_and_gaps_forward<I>(
+    digits: I,
+) -> impl Iterator<Item = Result<[FileSystemEntry; 2], DiskMapError>>
+where
+    I: Iterator<Item = Result<u8, DiskMapError>>,
+{
+    digits
+        .chain(once(Ok(0)))
         .tuples()
         .scan(0usize, |acc, (entry, blank)| {
-            let file = FileSystemEntry {
-                id: *acc,
-                length: entry,
-            };
-            let blank = FileSystemEntry {
-                id: EMPTY,
-                length: blank,
-            };
+            let entries = entry.and_then(|entry| {
+                let file = FileSystemEntry {
+                    id: *acc,
+                    length: entry as usize,
+                };
+                let blank = FileSystemEntry {
+                    id: EMPTY,
+                    length: blank? as usize,
+                };
+                Ok([file, blank])
+            });
             *acc += 1;
-            Some([file, blank])
+            Some(entries)
         })
 }
 
-fn parse_entries_backward<'a>(input: &'a str) -> impl Iterator<Item = FileSystemEntry> + 'a {
-    let max_id = (input.len() - 1) / 2;
-    input
-        .bytes()
-        .into_iter()
-        .rev()
+/// Expects the digits of a map of length `len` in reverse order, starting with the last file.
+fn parse_entries_backward<I>(
+    digits_reversed: I,
+    len: usize,
+) -> impl Iterator<Item = Result<FileSystemEntry, DiskMapError>>
+where
+    I: Iterator<Item = Result<u8, DiskMapError>>,
+{
+    let max_id = len.saturating_sub(1) / 2;
+    digits_reversed
         .step_by(2)
-        .map(|b| (b - '0' as u8) as usize)
         .enumerate()
-        .map(move |(index, entry)| FileSystemEntry {
-            id: max_id - index,
-            length: entry,
+        .map(move |(index, entry)| {
+            Ok(FileSystemEntry {
+                id: max_id - index,
+                length: entry? as usize,
+            })
         })
 }
 
-fn yield_entry(entry: FileSystemEntry, index: &mut usize, total: &mut usize) {
-    let id = entry.id as usize;
-    *total += *index * id * entry.length as usize;
-    *total += id * ((entry.length as usize * (entry.length as usize - 1)) / 2);
-    *index += entry.length as usize;
+/// An unsigned integer type that a checksum can be accumulated into without silently wrapping.
+pub trait Checksum: Copy + Default {
+    fn from_usize(n: usize) -> Option<Self>;
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! impl_checksum {
+    ($($t:ty),*) => {
+        $(
+            impl Checksum for $t {
+                fn from_usize(n: usize) -> Option<Self> {
+                    n.try_into().ok()
+                }
+
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$t>::checked_add(self, rhs)
+                }
+
+                fn checked_mul(self, rhs: Self) -> Option<Self> {
+                    <$t>::checked_mul(self, rhs)
+                }
+            }
+        )*
+    };
+}
+
+impl_checksum!(u32, u64, usize, u128);
+
+fn yield_entry<C: Checksum>(
+    entry: FileSystemEntry,
+    index: &mut C,
+    total: &mut C,
+) -> Result<(), DiskMapError> {
+    let checked = || {
+        let id = C::from_usize(entry.id)?;
+        let length = C::from_usize(entry.length)?;
+        let triangle = C::from_usize((entry.length * entry.length.saturating_sub(1)) / 2)?;
+        let new_total = total
+            .checked_add(index.checked_mul(id)?.checked_mul(length)?)?
+            .checked_add(id.checked_mul(triangle)?)?;
+        Some((new_total, index.checked_add(length)?))
+    };
+    let (new_total, new_index) =
+        checked().ok_or(DiskMapError::ChecksumOverflow { file_id: entry.id })?;
+    *total = new_total;
+    *index = new_index;
+    Ok(())
 }
 
 #[aoc(day9, part1)]
-pub fn part1(input: &str) -> usize {
-    let mut input_forward = parse_entries_and_gaps_forward(input);
-    let mut non_empty_backward = parse_entries_backward(input);
+pub fn part1(input: &str) -> Result<usize, AocError> {
+    Ok(part1_with(input)?)
+}
+
+#[aoc(day9, part1, Wide)]
+pub fn part1_wide(input: &str) -> Result<u128, AocError> {
+    Ok(part1_with(input)?)
+}
+
+pub fn part1_with<C: Checksum>(input: &str) -> Result<C, DiskMapError> {
+    let digits = parse_disk_map(input)?;
+    checksum_compacted_blocks(
+        digits.iter().map(|b| Ok(b - b'0')),
+        digits.iter().rev().map(|b| Ok(b - b'0')),
+        digits.len(),
+    )
+}
+
+/// Computes the part 1 checksum without reading the whole disk map into memory, walking a
+/// forward and a backward cursor over the reader in buffered chunks.
+pub fn part1_from_reader<C: Checksum, R: Read + Seek>(reader: R) -> Result<C, DiskMapError> {
+    part1_from_reader_chunked(reader, READ_CHUNK_SIZE)
+}
+
+fn part1_from_reader_chunked<C: Checksum, R: Read + Seek>(
+    reader: R,
+    chunk_size: usize,
+) -> Result<C, DiskMapError> {
+    let reader = RefCell::new(reader);
+    let mut range = trimmed_range(&reader, chunk_size)?;
+    // The cursors skip the gaps read backward, the trailing gap and everything past the point
+    // where they meet, so check every byte up front as `parse_disk_map` does.
+    for byte in ChunkedBytes::new(&reader, range.clone(), Direction::Forward, chunk_size) {
+        let (position, byte) = byte?;
+        if !byte.is_ascii_digit() {
+            return Err(DiskMapError::InvalidDigit {
+                position: position as usize,
+                byte,
+            });
+        }
+    }
+    if (range.end - range.start).is_multiple_of(2) {
+        range.end = range.end.saturating_sub(1).max(range.start);
+    }
+    let len = (range.end - range.start) as usize;
+
+    let digits = |direction| {
+        ChunkedBytes::new(&reader, range.clone(), direction, chunk_size)
+            .map(|byte| Ok(byte?.1 - b'0'))
+    };
+    checksum_compacted_blocks(digits(Direction::Forward), digits(Direction::Backward), len)
+}
+
+fn checksum_compacted_blocks<C, F, B>(
+    digits_forward: F,
+    digits_backward: B,
+    len: usize,
+) -> Result<C, DiskMapError>
+where
+    C: Checksum,
+    F: Iterator<Item = Result<u8, DiskMapError>>,
+    B: Iterator<Item = Result<u8, DiskMapError>>,
+{
+    if len == 0 {
+        return Ok(C::default());
+    }
+
+    let mut input_forward = parse_entries_and_gaps_forward(digits_forward);
+    let mut non_empty_backward = parse_entries_backward(digits_backward, len);
 
     let mut item_forward: FileSystemEntry;
     let mut blank_forward: FileSystemEntry;
-    [item_forward, blank_forward] = input_forward.next().unwrap();
-    let mut item_backward = non_empty_backward.next().unwrap();
+    [item_forward, blank_forward] = input_forward.next().unwrap()?;
+    let mut item_backward = non_empty_backward.next().unwrap()?;
     let mut already_consumed_from_backward_cursor = 0usize;
 
-    let mut index = 0usize;
-    let mut total = 0usize;
+    let mut index = C::default();
+    let mut total = C::default();
 
     loop {
         if item_forward.id >= item_backward.id {
-            if already_consumed_from_backward_cursor > 0 {
+            if item_forward.id == item_backward.id {
                 yield_entry(
                     FileSystemEntry {
                         id: item_backward.id,
@@ -79,16 +387,16 @@ pub fn part1(input: &str) -> usize {
                     },
                     &mut index,
                     &mut total,
-                );
+                )?;
             }
-            return total;
+            return Ok(total);
         }
 
-        yield_entry(item_forward, &mut index, &mut total);
+        yield_entry(item_forward, &mut index, &mut total)?;
 
         let mut gap_size = blank_forward.length;
         if gap_size == 0 {
-            [item_forward, blank_forward] = input_forward.next().unwrap();
+            [item_forward, blank_forward] = input_forward.next().unwrap()?;
             continue;
         }
 
@@ -104,10 +412,10 @@ pub fn part1(input: &str) -> usize {
                     },
                     &mut index,
                     &mut total,
-                );
+                )?;
                 already_consumed_from_backward_cursor = 0;
-                item_backward = non_empty_backward.next().unwrap();
-                if gap_size == 0 {
+                item_backward = non_empty_backward.next().unwrap()?;
+                if gap_size == 0 || item_backward.id <= item_forward.id {
                     break;
                 }
             } else {
@@ -118,13 +426,13 @@ pub fn part1(input: &str) -> usize {
                     },
                     &mut index,
                     &mut total,
-                );
+                )?;
                 already_consumed_from_backward_cursor += gap_size;
                 break;
             }
         }
 
-        [item_forward, blank_forward] = input_forward.next().unwrap();
+        [item_forward, blank_forward] = input_forward.next().unwrap()?;
     }
 }
 
@@ -173,16 +481,15 @@ impl Ord for PinnedFileSystemEntry {
     }
 }
 
-fn parse_entries_and_gaps_forward_part_2<'a>(
-    input: &'a str,
-) -> impl Iterator<Item = (PinnedFileSystemEntry, Gap)> + 'a {
+fn parse_entries_and_gaps_forward_part_2(
+    digits: &[u8],
+) -> impl Iterator<Item = (PinnedFileSystemEntry, Gap)> + '_ {
     let mut index = 0usize;
     let mut location = 0usize;
 
-    input
-        .bytes()
-        .into_iter()
-        .map(|b| (b - ('0' as u8)) as usize)
+    digits
+        .iter()
+        .map(|b| (b - b'0') as usize)
         .chain(once(0))
         .tuples::<(_, _)>()
         .map(move |(entry_len, gap_len)| {
@@ -202,12 +509,56 @@ fn parse_entries_and_gaps_forward_part_2<'a>(
         })
 }
 
-#[aoc(day9, part2)]
-pub fn part2(input: &str) -> usize {
-    let mut gaps: [VecDeque<u32>; 10] = Default::default();
-    let mut entries: Vec<PinnedFileSystemEntry> = Vec::with_capacity(input.len() / 2);
-    for (entry, gap) in parse_entries_and_gaps_forward_part_2(input) {
-        gaps[gap.length].push_back(gap.start_index as u32);
+/// Tracks the start index of every free gap, bucketed by gap length (0 through 9).
+trait GapIndex: Default {
+    fn insert(&mut self, gap: Gap);
+    fn leftmost_of_length(&self, length: usize) -> Option<usize>;
+    fn remove_leftmost_of_length(&mut self, length: usize);
+}
+
+/// Keeps each bucket sorted in a deque, so splitting a gap is an O(n) insert.
+#[derive(Default)]
+struct DequeGapIndex([VecDeque<usize>; 10]);
+
+impl GapIndex for DequeGapIndex {
+    fn insert(&mut self, gap: Gap) {
+        let bucket = &mut self.0[gap.length];
+        let insertion_point = bucket.partition_point(|&start| start < gap.start_index);
+        bucket.insert(insertion_point, gap.start_index);
+    }
+
+    fn leftmost_of_length(&self, length: usize) -> Option<usize> {
+        self.0[length].front().copied()
+    }
+
+    fn remove_leftmost_of_length(&mut self, length: usize) {
+        self.0[length].pop_front();
+    }
+}
+
+/// Keeps each bucket in a min-heap, so every operation is O(log n).
+#[derive(Default)]
+struct HeapGapIndex([BinaryHeap<Reverse<usize>>; 10]);
+
+impl GapIndex for HeapGapIndex {
+    fn insert(&mut self, gap: Gap) {
+        self.0[gap.length].push(Reverse(gap.start_index));
+    }
+
+    fn leftmost_of_length(&self, length: usize) -> Option<usize> {
+        self.0[length].peek().map(|Reverse(start)| *start)
+    }
+
+    fn remove_leftmost_of_length(&mut self, length: usize) {
+        self.0[length].pop();
+    }
+}
+
+fn compact_whole_files<G: GapIndex, C: Checksum>(digits: &[u8]) -> Result<C, DiskMapError> {
+    let mut gaps = G::default();
+    let mut entries: Vec<PinnedFileSystemEntry> = Vec::with_capacity(digits.len() / 2 + 1);
+    for (entry, gap) in parse_entries_and_gaps_forward_part_2(digits) {
+        gaps.insert(gap);
         entries.push(entry);
     }
 
@@ -215,12 +566,17 @@ pub fn part2(input: &str) -> usize {
         .into_iter()
         .rev()
         .map(|entry| {
-            let mut maybe_fillable_gap: Option<(u32, usize)> = None;
-            let mut earliest_start_index = entry.start_index as u32;
-            for l in entry.length..10 {
-                if gaps[l].len() > 0 && gaps[l][0] < earliest_start_index {
-                    earliest_start_index = gaps[l][0];
-                    maybe_fillable_gap = Some((gaps[l][0], l));
+            let mut maybe_fillable_gap: Option<Gap> = None;
+            let mut earliest_start_index = entry.start_index;
+            for length in entry.length..10 {
+                if let Some(start_index) = gaps.leftmost_of_length(length) {
+                    if start_index < earliest_start_index {
+                        earliest_start_index = start_index;
+                        maybe_fillable_gap = Some(Gap {
+                            start_index,
+                            length,
+                        });
+                    }
                 }
             }
 
@@ -228,52 +584,277 @@ pub fn part2(input: &str) -> usize {
                 return entry;
             };
 
-            gaps[gap.1].pop_front();
-
-            if gap.1 > entry.length {
-                let new_gap_length = gap.1 - entry.length;
-                let new_gap_start_index = gap.0 + entry.length as u32;
-                let gap_insertion_point = gaps[new_gap_length]
-                    .binary_search_by(|gap| gap.cmp(&new_gap_start_index))
-                    .unwrap_err();
-                gaps[new_gap_length].insert(
-                    gap_insertion_point,
-                    new_gap_start_index
-                );
+            gaps.remove_leftmost_of_length(gap.length);
+
+            if gap.length > entry.length {
+                gaps.insert(Gap {
+                    start_index: gap.start_index + entry.length,
+                    length: gap.length - entry.length,
+                });
             }
 
             PinnedFileSystemEntry {
-                start_index: gap.0 as usize,
+                start_index: gap.start_index,
                 id: entry.id,
                 length: entry.length,
             }
         })
-        .fold(0usize, |mut total, mut entry| {
+        .try_fold(C::default(), |mut total, entry| {
+            let mut index = C::from_usize(entry.start_index)
+                .ok_or(DiskMapError::ChecksumOverflow { file_id: entry.id })?;
             yield_entry(
                 FileSystemEntry {
                     id: entry.id,
                     length: entry.length,
                 },
-                &mut entry.start_index,
+                &mut index,
                 &mut total,
-            );
-            total
+            )?;
+            Ok(total)
         })
 }
 
+#[aoc(day9, part2)]
+pub fn part2(input: &str) -> Result<usize, AocError> {
+    Ok(compact_whole_files::<HeapGapIndex, _>(parse_disk_map(
+        input,
+    )?)?)
+}
+
+#[aoc(day9, part2, Wide)]
+pub fn part2_wide(input: &str) -> Result<u128, AocError> {
+    Ok(compact_whole_files::<HeapGapIndex, _>(parse_disk_map(
+        input,
+    )?)?)
+}
+
+#[aoc(day9, part2, Deque)]
+pub fn part2_deque(input: &str) -> Result<usize, AocError> {
+    Ok(compact_whole_files::<DequeGapIndex, _>(parse_disk_map(
+        input,
+    )?)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::Lcg;
+    use std::io::Cursor;
 
     #[test]
     fn test_part1() {
         let input = "2333133121414131402";
-        assert_eq!(part1(input), 1928);
+        assert_eq!(part1(input), Ok(1928));
     }
 
     #[test]
     fn test_part2() {
         let input = "2333133121414131402";
-        assert_eq!(part2(input), 2858);
+        assert_eq!(part2(input), Ok(2858));
+    }
+
+    #[test]
+    fn test_gap_indexes_agree() {
+        let mut rng = Lcg::new(54321);
+        for length in 1..200 {
+            let input: String = (0..length)
+                .map(|_| char::from(b'0' + rng.below(10) as u8))
+                .collect();
+            assert_eq!(part2(&input), part2_deque(&input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_part1_from_reader() {
+        let input = "2333133121414131402";
+        assert_eq!(part1_from_reader(Cursor::new(input)), Ok(1928usize));
+        assert_eq!(part1_from_reader(Cursor::new("\n 12345\r\n")), Ok(60usize));
+        assert_eq!(part1_from_reader(Cursor::new(" \n")), Ok(0usize));
+        assert_eq!(part1_from_reader(Cursor::new("12")), Ok(0usize));
+    }
+
+    #[test]
+    fn test_part1_from_reader_across_chunks() {
+        let mut rng = Lcg::new(2024);
+        for length in 1..60 {
+            let input: String = (0..length)
+                .map(|_| char::from(b'0' + rng.below(10) as u8))
+                .chain("\n".chars())
+                .collect();
+            for chunk_size in [1, 2, 3, 7, 64] {
+                assert_eq!(
+                    part1_from_reader_chunked::<usize, _>(Cursor::new(&input), chunk_size),
+                    part1_with(&input),
+                    "{} with chunks of {}",
+                    input,
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_part1_from_reader_invalid_digit() {
+        assert_eq!(
+            part1_from_reader_chunked::<usize, _>(Cursor::new("  1234x5\n"), 3),
+            Err(DiskMapError::InvalidDigit {
+                position: 6,
+                byte: b'x',
+            })
+        );
+    }
+
+    #[test]
+    fn test_reader_and_str_agree_on_invalid_input() {
+        // Every map over a small alphabet, so invalid bytes land in gaps read backward, in the
+        // trailing gap and past the point where the cursors meet.
+        let mut inputs = vec![String::new()];
+        for len in 1..=5 {
+            let shorter = inputs
+                .iter()
+                .filter(|i| i.len() == len - 1)
+                .cloned()
+                .collect_vec();
+            inputs.extend(
+                shorter
+                    .iter()
+                    .flat_map(|i| "0129x".chars().map(move |c| format!("{}{}", i, c))),
+            );
+        }
+        for input in &inputs {
+            let expected = part1_with::<u64>(input);
+            for chunk_size in [1, 3, 64] {
+                assert_eq!(
+                    part1_from_reader_chunked::<u64, _>(Cursor::new(input), chunk_size),
+                    expected,
+                    "{:?}",
+                    input
+                );
+            }
+        }
+        assert_eq!(
+            part1_from_reader::<u64, _>(Cursor::new("1x")),
+            Err(DiskMapError::InvalidDigit {
+                position: 1,
+                byte: b'x',
+            })
+        );
+    }
+
+    #[test]
+    fn test_checksum_overflow() {
+        let input = "9".repeat(100_001);
+        assert!(matches!(
+            part1_with::<u32>(&input),
+            Err(DiskMapError::ChecksumOverflow { .. })
+        ));
+        assert_eq!(
+            part1_with::<u64>(&input).map(u128::from),
+            part1_with(&input)
+        );
+        assert_eq!(part2_wide("2333133121414131402"), Ok(2858));
+    }
+
+    #[test]
+    fn test_wide_checksum_beyond_u64() {
+        let input = "9".repeat(3_000_001);
+        let wide = part1_wide(&input).unwrap();
+        assert!(wide > u64::MAX as u128);
+        assert!(matches!(
+            part1_with::<u64>(&input),
+            Err(DiskMapError::ChecksumOverflow { .. })
+        ));
+        assert_eq!(part1_from_reader(Cursor::new(&input)), Ok(wide));
+
+        assert!(part2_wide(&input).unwrap() > u64::MAX as u128);
+        assert!(matches!(
+            compact_whole_files::<HeapGapIndex, u64>(input.as_bytes()),
+            Err(DiskMapError::ChecksumOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_trailing_whitespace() {
+        let input = "2333133121414131402\n";
+        assert_eq!(part1(input), Ok(1928));
+        assert_eq!(part2(input), Ok(2858));
+        assert_eq!(part1("12345\r\n"), Ok(60));
+    }
+
+    #[test]
+    fn test_trailing_gap_is_ignored() {
+        assert_eq!(part1("2333133121414131402"), part1("23331331214141314025"));
+        assert_eq!(part2("2333133121414131402"), part2("23331331214141314025"));
+        assert_eq!(part1("12"), Ok(0));
+        assert_eq!(part2("12"), Ok(0));
+    }
+
+    #[test]
+    fn test_degenerate_maps() {
+        assert_eq!(part1(""), Ok(0));
+        assert_eq!(part2("\n"), Ok(0));
+        assert_eq!(part1("1"), Ok(0));
+        assert_eq!(part1("101"), Ok(1));
+        assert_eq!(part2("101"), Ok(1));
+    }
+
+    fn part1_by_expansion(input: &str) -> usize {
+        let mut blocks: Vec<usize> = input
+            .trim()
+            .bytes()
+            .enumerate()
+            .flat_map(|(i, b)| {
+                let id = if i % 2 == 0 { i / 2 } else { EMPTY };
+                std::iter::repeat_n(id, (b - b'0') as usize)
+            })
+            .collect();
+        let mut left = 0;
+        loop {
+            while blocks.last() == Some(&EMPTY) {
+                blocks.pop();
+            }
+            if left >= blocks.len() {
+                break;
+            }
+            if blocks[left] == EMPTY {
+                blocks[left] = blocks.pop().unwrap();
+            }
+            left += 1;
+        }
+        blocks.iter().enumerate().map(|(i, id)| i * id).sum()
+    }
+
+    #[test]
+    fn test_part1_matches_expansion() {
+        let mut rng = Lcg::new(12345);
+        for length in 1..40 {
+            let input: String = (0..length)
+                .map(|_| char::from(b'0' + rng.below(10) as u8))
+                .collect();
+            assert_eq!(part1(&input), Ok(part1_by_expansion(&input)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_digit() {
+        let error = DiskMapError::InvalidDigit {
+            position: 5,
+            byte: b'x',
+        };
+        assert_eq!(part1(" 1234x5"), Err(error.into()));
+        assert_eq!(part2(" 1234x5"), Err(error.into()));
+        assert_eq!(
+            part1("12\n34"),
+            Err(DiskMapError::InvalidDigit {
+                position: 2,
+                byte: b'\n'
+            }
+            .into())
+        );
+        assert_eq!(error.to_string(), "invalid disk map digit 'x' at byte 5");
+        assert_eq!(
+            part1(" 1234x5").unwrap_err().to_string(),
+            "invalid input at byte 5: invalid disk map digit 'x'"
+        );
     }
 }
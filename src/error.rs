@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::{fmt, io};
+
+/// Where in the input an error was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    /// A 0-based byte offset into the input.
+    Offset(usize),
+    /// A 1-based line.
+    Line(usize),
+    /// A 1-based line and byte column.
+    LineColumn { line: usize, column: usize },
+}
+
+impl Position {
+    /// The line and column of a byte offset into `input`.
+    pub fn of_offset(input: &str, offset: usize) -> Position {
+        let before = &input.as_bytes()[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        Position::LineColumn {
+            line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+            column: offset - line_start + 1,
+        }
+    }
+
+    /// The position in a piece of input that starts after `lines` whole lines, as a position in
+    /// the whole input. Byte offsets are left as they are.
+    pub fn after_lines(self, lines: usize) -> Position {
+        match self {
+            Position::Offset(offset) => Position::Offset(offset),
+            Position::Line(line) => Position::Line(line + lines),
+            Position::LineColumn { line, column } => Position::LineColumn {
+                line: line + lines,
+                column,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Position::Offset(offset) => write!(f, "byte {}", offset),
+            Position::Line(line) => write!(f, "line {}", line),
+            Position::LineColumn { line, column } => {
+                write!(f, "line {}, column {}", line, column)
+            }
+        }
+    }
+}
+
+/// An error from any day's solver. Each day has its own error type with the details, and
+/// converts it into one of these broad kinds at the solver boundary: input that does not lex,
+/// tokens in the wrong shape, or arithmetic that does not fit the answer type.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AocError {
+    Lex {
+        position: Position,
+        message: String,
+    },
+    Structure {
+        position: Option<Position>,
+        message: String,
+    },
+    Arithmetic {
+        position: Option<Position>,
+        message: String,
+    },
+    Io(io::ErrorKind),
+}
+
+impl AocError {
+    pub fn lex(position: Position, message: impl Into<String>) -> Self {
+        AocError::Lex {
+            position,
+            message: message.into(),
+        }
+    }
+
+    pub fn structure(position: Option<Position>, message: impl Into<String>) -> Self {
+        AocError::Structure {
+            position,
+            message: message.into(),
+        }
+    }
+
+    pub fn arithmetic(position: Option<Position>, message: impl Into<String>) -> Self {
+        AocError::Arithmetic {
+            position,
+            message: message.into(),
+        }
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            AocError::Lex { position, .. } => Some(*position),
+            AocError::Structure { position, .. } | AocError::Arithmetic { position, .. } => {
+                *position
+            }
+            AocError::Io(_) => None,
+        }
+    }
+
+    /// The error with its position moved by [`Position::after_lines`], for errors found in a
+    /// piece of the input.
+    pub fn after_lines(self, lines: usize) -> Self {
+        match self {
+            AocError::Lex { position, message } => AocError::Lex {
+                position: position.after_lines(lines),
+                message,
+            },
+            AocError::Structure { position, message } => AocError::Structure {
+                position: position.map(|p| p.after_lines(lines)),
+                message,
+            },
+            AocError::Arithmetic { position, message } => AocError::Arithmetic {
+                position: position.map(|p| p.after_lines(lines)),
+                message,
+            },
+            AocError::Io(kind) => AocError::Io(kind),
+        }
+    }
+}
+
+impl fmt::Display for AocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, message) = match self {
+            AocError::Lex { message, .. } => ("invalid input", message),
+            AocError::Structure { message, .. } => ("malformed input", message),
+            AocError::Arithmetic { message, .. } => ("arithmetic error", message),
+            AocError::Io(kind) => return write!(f, "failed to read input: {}", kind),
+        };
+        match self.position() {
+            Some(position) => write!(f, "{} at {}: {}", kind, position, message),
+            None => write!(f, "{}: {}", kind, message),
+        }
+    }
+}
+
+/// aoc-runner prints a failed solver's error with `{:#?}`, so this shows the message rather than
+/// the variant's fields.
+impl fmt::Debug for AocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for AocError {}
+
+impl From<io::Error> for AocError {
+    fn from(error: io::Error) -> Self {
+        AocError::Io(error.kind())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_of_offset() {
+        let input = "ab\ncde\n\nf";
+        assert_eq!(
+            Position::of_offset(input, 0),
+            Position::LineColumn { line: 1, column: 1 }
+        );
+        assert_eq!(
+            Position::of_offset(input, 5),
+            Position::LineColumn { line: 2, column: 3 }
+        );
+        assert_eq!(
+            Position::of_offset(input, 8),
+            Position::LineColumn { line: 4, column: 1 }
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let error = AocError::lex(Position::Offset(3), "unexpected 'x'");
+        assert_eq!(error.to_string(), "invalid input at byte 3: unexpected 'x'");
+        assert_eq!(format!("{:#?}", error), error.to_string());
+        assert_eq!(
+            AocError::arithmetic(None, "answer overflowed").to_string(),
+            "arithmetic error: answer overflowed"
+        );
+        assert_eq!(
+            AocError::structure(Some(Position::Line(2)), "equation has no terms").to_string(),
+            "malformed input at line 2: equation has no terms"
+        );
+    }
+}
@@ -6,6 +6,9 @@ pub mod day1;
 pub mod day2;
 pub mod day3;
 pub mod day4;
+pub mod day5;
+pub mod day6;
+pub mod error;
 
 pub mod day7;
 pub mod day9;